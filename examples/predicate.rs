@@ -0,0 +1,35 @@
+//! This example demonstrates testing of a type that can't be compared using `PartialEq`.
+
+use std::io;
+
+/// Hypothetical type containing a function pointer so it can't derive `PartialEq` or `Debug`.
+struct Command {
+    id: u16,
+    handler: fn(u16) -> u16,
+}
+
+fn double(x: u16) -> u16 {
+    x.wrapping_mul(2)
+}
+
+impl Command {
+    /// Decodes the command from a reader
+    #[allow(clippy::unused_io_amount)] // the bug is intentional
+    fn from_reader<R: io::Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0u8; 2];
+        reader.read(&mut buf)?; // this line is wrong and will get reported in the backtrace
+        Ok(Command { id: u16::from_le_bytes(buf), handler: double, })
+    }
+}
+
+/// Tests the implementation of `Command::from_reader`.
+///
+/// This is main for simplicity but in real life you'd use `#[test]`
+fn main() {
+    io_check::test_read_check(&[21, 0], |reader| Command::from_reader(reader).unwrap(), |command| {
+        match (command.handler)(command.id) {
+            42 => Ok(()),
+            result => Err(format!("the command with id {} computed {} instead of 42", command.id, result)),
+        }
+    });
+}
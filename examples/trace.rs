@@ -0,0 +1,38 @@
+//! This example demonstrates tracing the IO calls made by the tested code, which shows exactly
+//! how it reacts to the readers provided by the crate.
+
+use std::io::{self, Read};
+use io_check::read::ReadTest;
+use io_check::trace::IoEvent;
+
+/// Hypothetical type that can be decoded from byte stream.
+#[derive(Debug, Eq, PartialEq)]
+struct Value(u16, Vec<u8>);
+
+impl Value {
+    /// Decodes the value from a reader
+    fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf)?;
+        let len = u16::from_le_bytes(buf);
+        let mut data = Vec::new();
+        reader.take(len.into()).read_to_end(&mut data)?;
+        Ok(Value(len, data))
+    }
+}
+
+/// Prints the trace of decoding `Value`.
+///
+/// This is main for simplicity but in real life you'd use `#[test]`
+fn main() {
+    ReadTest::new(&[3, 0, 1, 2, 3])
+        .trace(|event| match event {
+            IoEvent::Run { split: Some(split) } => println!("run with input split at {}", split),
+            IoEvent::Run { split: None } => println!("run with breaking reader"),
+            event => println!("    {:?}", event),
+        })
+        .run(|reader| {
+            let value = Value::from_reader(reader).unwrap();
+            assert_eq!(value, Value(3, vec![1, 2, 3]));
+        });
+}
@@ -9,6 +9,7 @@ struct Value(u16, u16);
 
 impl Value {
     /// Decodes the value from a reader
+    #[allow(clippy::unused_io_amount)] // the bug is intentional
     fn from_reader<R: io::Read>(mut reader: R) -> io::Result<Self> {
         let mut buf = [0u8; 2];
         reader.read_exact(&mut buf)?;
@@ -8,6 +8,7 @@ struct Value(u16, u16);
 
 impl Value {
     /// Encodes the value to the writer
+    #[allow(clippy::unused_io_amount)] // the bug is intentional
     fn to_writer<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
         writer.write_all(&self.0.to_le_bytes())?;
         writer.write(&self.1.to_le_bytes())?; // this line is wrong and will get reported in the backtrace
@@ -1,38 +1,75 @@
-enum Operation {
+use std::fmt;
+use std::panic::Location;
+
+/// Location of the call of the test function, available with `rust_1_46` feature.
+pub type TestLocation = Option<&'static Location<'static>>;
+
+/// Returns the location of the caller if `#[track_caller]` is available.
+#[cfg_attr(feature = "rust_1_46", track_caller)]
+#[cfg_attr(feature = "rust_1_46", clippy::msrv = "1.46")]
+pub fn test_location() -> TestLocation {
+    #[cfg(feature = "rust_1_46")]
+    {
+        Some(Location::caller())
+    }
+    #[cfg(not(feature = "rust_1_46"))]
+    {
+        None
+    }
+}
+
+/// Displays which test failed.
+pub struct DisplayTest(pub TestLocation);
+
+impl fmt::Display for DisplayTest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(location) => write!(f, "io_check test at {}:{}", location.file(), location.line()),
+            None => write!(f, "test"),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Operation {
     Read,
     Write,
 }
 
 pub struct DisplayBacktrace<'a> {
-    #[cfg_attr(not(feature = "backtrace"), allow(unused))]
+    #[cfg_attr(any(miri, not(feature = "backtrace")), allow(unused))]
     backtrace: &'a Option<Backtrace>,
-    #[cfg_attr(not(feature = "backtrace"), allow(unused))]
+    #[cfg_attr(any(miri, not(feature = "backtrace")), allow(unused))]
     operation: Operation
 }
 
 impl<'a> DisplayBacktrace<'a> {
-    pub fn read(backtrace: &'a Option<Backtrace>) -> Self {
+    pub fn new(backtrace: &'a Option<Backtrace>, operation: Operation) -> Self {
         DisplayBacktrace {
             backtrace,
-            operation: Operation::Read,
-        }
-    }
-
-    pub fn write(backtrace: &'a Option<Backtrace>) -> Self {
-        DisplayBacktrace {
-            backtrace,
-            operation: Operation::Write,
+            operation,
         }
     }
 }
 
-#[cfg(feature = "backtrace")]
+// Capturing backtraces under Miri is extremely slow (if supported at all) so Miri runs always
+// use the implementation without backtraces, trading diagnostics for speed.
+#[cfg(all(not(miri), feature = "backtrace"))]
 mod imp {
     use std::fmt;
     use std::panic::AssertUnwindSafe;
+    #[cfg(feature = "rust_1_46")]
+    use std::panic::Location;
     use super::Operation;
 
-    pub use backtrace::Backtrace;
+    use backtrace::BacktraceSymbol;
+
+    pub struct Backtrace {
+        frames: backtrace::Backtrace,
+        // With `rust_1_46` the exact location of the culprit is known, no need to guess from symbols
+        #[cfg(feature = "rust_1_46")]
+        location: &'static Location<'static>,
+    }
 
     // safe because the only modification we do is assigning which can not panic
     // we also don't read from it
@@ -44,13 +81,123 @@ mod imp {
         }
 
         #[inline(always)] // remove this useless frame from backtrace
+        #[cfg_attr(feature = "rust_1_46", track_caller)]
+        #[cfg_attr(feature = "rust_1_46", clippy::msrv = "1.46")]
         pub fn capture(&mut self) {
-            *(self.0).0 = Some(Backtrace::new_unresolved());
+            *(self.0).0 = Some(Backtrace {
+                frames: backtrace::Backtrace::new_unresolved(),
+                #[cfg(feature = "rust_1_46")]
+                location: Location::caller(),
+            });
         }
     }
 
     pub fn resolve(storage: &mut Option<Backtrace>) {
-        storage.as_mut().map(|storage| storage.resolve());
+        if let Some(storage) = storage.as_mut() {
+            storage.frames.resolve();
+        }
+    }
+
+    /// Returns the number of frames to print configured by `IO_CHECK_BACKTRACE_FRAMES`.
+    fn max_frames() -> Option<usize> {
+        std::env::var("IO_CHECK_BACKTRACE_FRAMES").ok()?.parse().ok()
+    }
+
+    fn write_location(f: &mut fmt::Formatter, symbol: &BacktraceSymbol) -> fmt::Result {
+        if let Some(file) = symbol.filename() {
+            write!(f, "at {}", file.display())?;
+            if let Some(line) = symbol.lineno() {
+                write!(f, ":{}", line)?;
+                if let Some(column) = symbol.colno() {
+                    write!(f, ":{}", column)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes at most `max` frames starting with the one at index `first`.
+    fn write_frames(f: &mut fmt::Formatter, backtrace: &backtrace::Backtrace, first: usize, max: usize) -> fmt::Result {
+        writeln!(f, "backtrace (at most {} frames starting with the culprit):\n", max)?;
+        for (i, frame) in backtrace.frames().iter().enumerate().skip(first).take(max) {
+            for symbol in frame.symbols() {
+                match symbol.name() {
+                    Some(name) => writeln!(f, "{:4}: {}", i, name)?,
+                    None => writeln!(f, "{:4}: <unknown>", i)?,
+                }
+                if symbol.filename().is_some() {
+                    write!(f, "             ")?;
+                    write_location(f, symbol)?;
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the symbol belongs to a `std` adaptor forwarding IO calls, e.g. `Take`.
+    ///
+    /// The adaptor calls the test reader or writer on behalf of the consumer so the consumer is
+    /// the culprit.
+    fn is_std_adaptor(symbol: &BacktraceSymbol) -> bool {
+        let name = match symbol.name() {
+            Some(name) => name.to_string(),
+            None => return false,
+        };
+        name.starts_with("<std::io::") || name.starts_with("std::io::") || name.starts_with("<&mut R as std::io::")
+    }
+
+    /// Finds the frame calling `read` or `write` of the test reader or writer.
+    fn find_culprit<'a>(backtrace: &'a backtrace::Backtrace, operation: &Operation) -> Option<(usize, &'a BacktraceSymbol)> {
+        let mut symbols = backtrace.frames()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, frame)| frame.symbols().iter().map(move |symbol| (i, symbol)));
+        let op_fn_name = match operation {
+            Operation::Read => "<io_check::read::TestReader as std::io::Read>::read::",
+            Operation::Write => "<io_check::write::TestWriter as std::io::Write>::write::",
+        };
+        while let Some((_, symbol)) = symbols.next() {
+            let is_test_reader_read = symbol.name().map(|name| name.to_string().starts_with(op_fn_name));
+            if is_test_reader_read == Some(true) {
+                return symbols.find(|(_, symbol)| !is_std_adaptor(symbol));
+            }
+        }
+        None
+    }
+
+    #[cfg(feature = "rust_1_46")]
+    fn write_culprit(f: &mut fmt::Formatter, backtrace: &Backtrace, culprit: Option<&BacktraceSymbol>) -> fmt::Result {
+        let location = backtrace.location;
+        match culprit.and_then(BacktraceSymbol::name) {
+            Some(name) => writeln!(f, "*******\nMost likely culprit in {}", name)?,
+            None => writeln!(f, "*******\nMost likely culprit:")?,
+        }
+        // the caller is a `std` adaptor, the location of the consumer is only known from symbols
+        match culprit {
+            Some(culprit) if location.file().contains("library/std/src/") && culprit.filename().is_some() => {
+                write!(f, "    ")?;
+                write_location(f, culprit)?;
+                writeln!(f, "\n*******")
+            },
+            _ => writeln!(f, "    at {}:{}:{}\n*******", location.file(), location.line(), location.column()),
+        }
+    }
+
+    #[cfg(not(feature = "rust_1_46"))]
+    fn write_culprit(f: &mut fmt::Formatter, _backtrace: &Backtrace, culprit: Option<&BacktraceSymbol>) -> fmt::Result {
+        if let Some(culprit) = culprit {
+            if let Some(name) = culprit.name() {
+                writeln!(f, "*******\nMost likely culprit in {}", name)?;
+                if culprit.filename().is_some() {
+                    write!(f, "    ")?;
+                    write_location(f, culprit)?;
+                    writeln!(f)?;
+                }
+                writeln!(f, "*******")?;
+            }
+        }
+        Ok(())
     }
 
     impl<'a> fmt::Display for super::DisplayBacktrace<'a> {
@@ -58,43 +205,21 @@ mod imp {
 
             match &self.backtrace {
                 Some(backtrace) => {
-                    let mut culprit = None;
-                    let mut symbols = backtrace.frames().iter().flat_map(|frame| frame.symbols());
-                    let op_fn_name = match self.operation {
-                        Operation::Read => "<io_check::read::TestReader as std::io::Read>::read::",
-                        Operation::Write => "<io_check::write::TestWriter as std::io::Write>::write::",
-                    };
-                    while let Some(symbol) = symbols.next() {
-                        let is_test_reader_read = symbol.name().map(|name| name.to_string().starts_with(op_fn_name));
-                        if is_test_reader_read == Some(true) {
-                            culprit = symbols.next();
-                            break;
-                        }
-                    }
-
-                    if let Some(culprit) = culprit {
-                        if let Some(name) = culprit.name() {
-                            writeln!(f, "*******\nMost likely culprit in {}", name)?;
-                            if let Some(file) = culprit.filename() {
-                                write!(f, "    at {}", file.display())?;
-                                if let Some(line) = culprit.lineno() {
-                                    write!(f, ":{}", line)?;
-                                    if let Some(column) = culprit.colno() {
-                                        write!(f, ":{}", column)?;
-                                    }
-                                }
-                                writeln!(f)?;
-                            }
-                            writeln!(f, "*******")?;
-                        }
+                    let culprit = find_culprit(&backtrace.frames, &self.operation);
+                    // the location is always known with `rust_1_46`
+                    let culprit_known = culprit.is_some() || cfg!(feature = "rust_1_46");
+                    write_culprit(f, backtrace, culprit.map(|(_, symbol)| symbol))?;
 
-                        if std::env::var("RUST_BACKTRACE").unwrap_or(String::new()) == "1" {
-                            write!(f, "backtrace:\n\n{:?}", backtrace)
-                        } else {
-                            write!(f, "Set RUST_BACKTRACE=1 environment variable to see the full backtrace")
+                    if !culprit_known {
+                        write!(f, "backtrace:\n\n{:?}", backtrace.frames)
+                    } else if std::env::var("RUST_BACKTRACE").unwrap_or_default() == "1" {
+                        match (max_frames(), culprit) {
+                            (Some(max), Some((culprit_frame, _))) => write_frames(f, &backtrace.frames, culprit_frame, max),
+                            (Some(max), None) => write_frames(f, &backtrace.frames, 0, max),
+                            (None, _) => write!(f, "backtrace:\n\n{:?}", backtrace.frames),
                         }
                     } else {
-                        write!(f, "backtrace:\n\n{:?}", backtrace)
+                        write!(f, "Set RUST_BACKTRACE=1 environment variable to see the full backtrace")
                     }
                 },
                 None => write!(f, "no backtrace found - the problem is most likely unrelated to flaky IO"),
@@ -104,7 +229,7 @@ mod imp {
 
 }
 
-#[cfg(all(not(feature = "backtrace"), not(feature = "rust_1_46")))]
+#[cfg(any(miri, all(not(feature = "backtrace"), not(feature = "std_backtrace"), not(feature = "rust_1_46"))))]
 mod imp {
     use std::panic::AssertUnwindSafe;
 
@@ -130,12 +255,70 @@ mod imp {
 
     impl<'a> fmt::Display for super::DisplayBacktrace<'a> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            write!(f, "backtrace unavailable - compile with `backtrace` or `rust_1_46` feature to get the location of incorrect IO handling")
+            if cfg!(miri) {
+                write!(f, "backtrace unavailable - backtraces are not captured when running under Miri")
+            } else {
+                write!(f, "backtrace unavailable - compile with `backtrace`, `std_backtrace` or `rust_1_46` feature to get the location of incorrect IO handling")
+            }
+        }
+    }
+}
+
+#[cfg(all(not(miri), not(feature = "backtrace"), feature = "std_backtrace"))]
+#[clippy::msrv = "1.65"]
+mod imp {
+    use std::panic::{AssertUnwindSafe, Location};
+    use std::backtrace::BacktraceStatus;
+
+    use std::fmt;
+
+    pub struct Backtrace {
+        // boxed to keep errors small, it's big compared to the rest
+        frames: Box<std::backtrace::Backtrace>,
+        location: &'static Location<'static>,
+    }
+
+    // safe because the only modification we do is assigning which can not panic
+    // we also don't read from it
+    pub struct BacktraceStorageMut<'a>(AssertUnwindSafe<&'a mut Option<Backtrace>>);
+
+    impl<'a> BacktraceStorageMut<'a> {
+        pub fn from_mut(storage: &'a mut Option<Backtrace>) -> Self {
+            BacktraceStorageMut(AssertUnwindSafe(storage))
+        }
+
+        #[track_caller]
+        pub fn capture(&mut self) {
+            *(self.0).0 = Some(Backtrace {
+                // respects `RUST_BACKTRACE` and `RUST_LIB_BACKTRACE` on its own
+                frames: Box::new(std::backtrace::Backtrace::capture()),
+                location: Location::caller(),
+            });
+        }
+    }
+
+    pub fn resolve(_storage: &mut Option<Backtrace>) {
+    }
+
+    impl<'a> fmt::Display for super::DisplayBacktrace<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.backtrace {
+                Some(backtrace) => {
+                    let location = backtrace.location;
+                    writeln!(f, "*******\nMost likely culprit in {}:{}:{}\n*******", location.file(), location.line(), location.column())?;
+                    match backtrace.frames.status() {
+                        BacktraceStatus::Captured => write!(f, "backtrace:\n\n{}", backtrace.frames),
+                        _ => write!(f, "Set RUST_BACKTRACE=1 environment variable to see the full backtrace"),
+                    }
+                },
+                None => write!(f, "no error location found - the problem is most likely unrelated to flaky IO"),
+            }
         }
     }
 }
 
-#[cfg(all(not(feature = "backtrace"), feature = "rust_1_46"))]
+#[cfg(all(not(miri), not(feature = "backtrace"), not(feature = "std_backtrace"), feature = "rust_1_46"))]
+#[clippy::msrv = "1.46"]
 mod imp {
     use std::panic::{AssertUnwindSafe, Location};
 
@@ -0,0 +1,207 @@
+//! Contains items related to testing of `BufRead` usage.
+
+use std::io::{self, BufRead};
+use std::panic::{catch_unwind, resume_unwind, UnwindSafe, RefUnwindSafe};
+
+use crate::backtrace_impl::{self, DisplayTest};
+use crate::error;
+
+/// Buffered reader limiting the amount of data returned by `fill_buf`.
+///
+/// This reader is created by [`test_bufread`] function and provided to closure to test consumers
+/// of the [`BufRead`] trait. Each `fill_buf` call returns at most a configured number of bytes
+/// even if more input is available, just like a `BufReader` with a small buffer or a network
+/// stream would. `Read` methods are implemented on top of `fill_buf` so they are limited too.
+///
+/// The reader panics if the consumer consumes more bytes than the last `fill_buf` call returned.
+///
+/// The reader behaves the same when used through `&mut dyn BufRead` since all the methods it
+/// implements are part of the trait object.
+pub struct TestBufReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    max_fill: usize,
+    // number of bytes returned by the last `fill_buf` and not consumed yet
+    available: usize,
+}
+
+impl<'a> TestBufReader<'a> {
+    fn new(input: &'a [u8], max_fill: usize) -> Self {
+        TestBufReader {
+            input,
+            pos: 0,
+            max_fill,
+            available: 0,
+        }
+    }
+}
+
+impl io::Read for TestBufReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for TestBufReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let end = self.input.len().min(self.pos + self.max_fill);
+        self.available = end - self.pos;
+        Ok(&self.input[self.pos..end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        assert!(amt <= self.available, "consumer consumed {} bytes but the last `fill_buf` call returned only {} unconsumed bytes", amt, self.available);
+        self.pos += amt;
+        self.available -= amt;
+    }
+}
+
+/// Configurable test of `BufRead` consumers.
+///
+/// [`test_bufread`] is equivalent to `BufReadTest::new(input).run(f)`.
+pub struct BufReadTest<'a> {
+    input: &'a [u8],
+    max_fill: Option<usize>,
+}
+
+impl<'a> BufReadTest<'a> {
+    /// Creates the test providing `input` to the closure.
+    pub fn new(input: &'a [u8]) -> Self {
+        BufReadTest {
+            input,
+            max_fill: None,
+        }
+    }
+
+    /// Sets the largest number of bytes returned by a `fill_buf` call.
+    ///
+    /// The closure is called with readers returning at most 1, 2, ... up to `max` bytes from
+    /// `fill_buf`. By default the limit goes up to the length of the input. Setting it is useful
+    /// for large inputs where the small limits are the interesting ones, e.g. to catch a consumer
+    /// assuming `fill_buf` always returns at least four bytes.
+    ///
+    /// Panics if `max` is zero since `fill_buf` returning no data means EOF.
+    pub fn max_fill(mut self, max: usize) -> Self {
+        assert!(max > 0, "the maximum number of bytes returned by `fill_buf` must be non-zero");
+        self.max_fill = Some(max);
+        self
+    }
+
+    /// Runs the test, panicking if it fails.
+    ///
+    /// The failure message contains the smallest limit of `fill_buf` at which the closure failed.
+    /// The panic is the only way the failure is reported: it doesn't go through a
+    /// [`Reporter`](crate::report::Reporter) and there's no `try_run` returning it.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run<F>(self, f: F) where F: Fn(TestBufReader<'_>) + UnwindSafe + RefUnwindSafe {
+        let location = backtrace_impl::test_location();
+        let input = self.input;
+        let max_fill = self.max_fill.unwrap_or(input.len()).max(1);
+        let failure = error::quiet_panics(|| (1..=max_fill).find_map(|limit| {
+            catch_unwind(|| f(TestBufReader::new(input, limit))).err().map(|unwind| (limit, unwind))
+        }));
+        if let Some((limit, unwind)) = failure {
+            match error::get_panic_message(&unwind) {
+                Some(msg) => panic!("{} failed when `fill_buf` returned at most {} bytes: {}", DisplayTest(location), limit, msg),
+                None => resume_unwind(unwind),
+            }
+        }
+    }
+}
+
+/// Tests whether the closure correctly handles `fill_buf` returning little data.
+///
+/// This works like [`test_read`](crate::test_read) but for consumers of [`BufRead`]. The closure
+/// is called with readers limiting the number of bytes returned by `fill_buf` to 1, 2 and so on up
+/// to the length of the input and should *panic* if the decoded values are not equal to the
+/// expected ones. See [`BufReadTest`] for configuration.
+#[cfg_attr(feature = "rust_1_46", track_caller)]
+pub fn test_bufread<F>(input: &[u8], f: F) where F: Fn(TestBufReader<'_>) + UnwindSafe + RefUnwindSafe {
+    BufReadTest::new(input).run(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read};
+    use super::{test_bufread, BufReadTest};
+
+    #[test]
+    fn read_until() {
+        test_bufread(b"hello\nworld\n", |mut reader| {
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line).unwrap();
+            assert_eq!(line, b"hello\n");
+            line.clear();
+            reader.read_until(b'\n', &mut line).unwrap();
+            assert_eq!(line, b"world\n");
+        });
+    }
+
+    #[test]
+    fn read_exact() {
+        test_bufread(&[1, 0, 2, 0], |mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [1, 0, 2, 0]);
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed when `fill_buf` returned at most 1 bytes: index out of bounds"]
+    fn fill_buf_indexed() {
+        test_bufread(&[1, 2, 3, 4, 5], |mut reader| {
+            // buggy: assumes at least four bytes are available
+            let tag = reader.fill_buf().unwrap()[3];
+            assert_eq!(tag, 4);
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed when `fill_buf` returned at most 2 bytes"]
+    fn max_fill() {
+        BufReadTest::new(&[1, 2, 3, 4, 5, 6, 7, 8]).max_fill(4).run(|mut reader| {
+            let buf = reader.fill_buf().unwrap();
+            // buggy: handles a single byte but assumes four bytes otherwise
+            let tag = if buf.len() == 1 { 4 } else { buf[3] };
+            assert_eq!(tag, 4);
+        });
+    }
+
+    #[test]
+    fn trait_object() {
+        fn read_line(reader: &mut dyn BufRead) -> Vec<u8> {
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line).unwrap();
+            line
+        }
+
+        test_bufread(b"hello\nworld", |mut reader| {
+            assert_eq!(read_line(&mut reader), b"hello\n");
+            assert_eq!(read_line(&mut reader), b"world");
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed when `fill_buf` returned at most 1 bytes: index out of bounds"]
+    fn trait_object_fill_buf_indexed() {
+        fn tag(reader: &mut dyn BufRead) -> u8 {
+            // buggy: assumes at least four bytes are available
+            reader.fill_buf().unwrap()[3]
+        }
+
+        test_bufread(&[1, 2, 3, 4, 5], |mut reader| assert_eq!(tag(&mut reader), 4));
+    }
+
+    #[test]
+    #[should_panic = "consumer consumed 2 bytes but the last `fill_buf` call returned only 1 unconsumed bytes"]
+    fn consume_too_much() {
+        test_bufread(&[1, 2], |mut reader| {
+            reader.fill_buf().unwrap();
+            reader.consume(2);
+        });
+    }
+}
@@ -0,0 +1,69 @@
+//! Testing of code moving data from a reader to a writer.
+
+use std::panic::{UnwindSafe, RefUnwindSafe};
+
+use crate::read::{self, ReadTest, TestReader};
+use crate::write::{self, TestWriter, WriteTest};
+
+/// Tests whether the closure copying data from a reader to a writer handles splits on both sides.
+///
+/// The closure accepts a reader returning `input` and a writer expecting `expected` and
+/// typically just calls `io::copy`. The read side is tested first: the reader splits the input
+/// while the writer accepts whole writes. Then the write side is tested the same way with the
+/// roles swapped. The failure message tells which side misbehaved.
+///
+/// For best results make sure no other inputs affect the test - the function should be pure.
+///
+/// ## Example
+///
+/// ```
+/// io_check::test_copy(b"hello", b"hello", |mut reader, mut writer| {
+///     std::io::copy(&mut reader, &mut writer).unwrap();
+/// });
+/// ```
+#[cfg_attr(feature = "rust_1_46", track_caller)]
+pub fn test_copy<F>(input: &[u8], expected: &[u8], f: F) where F: Fn(TestReader<'_>, TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+    if let Err(failure) = ReadTest::new(input).try_run(|reader| write::write_whole(expected, |writer| f(reader, writer))) {
+        failure.copy_side().panic();
+    }
+    if let Err(failure) = WriteTest::new(expected).try_run(|writer| read::read_whole(input, |reader| f(reader, writer))) {
+        failure.copy_side().panic();
+    }
+}
+
+#[cfg(test)]
+// the tests intentionally contain buggy IO code
+#[allow(clippy::unused_io_amount)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use super::test_copy;
+
+    #[test]
+    fn io_copy() {
+        test_copy(b"hello world", b"hello world", |mut reader, mut writer| {
+            io::copy(&mut reader, &mut writer).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "(read side of the copy) failed at position 1"]
+    fn short_read() {
+        test_copy(b"hello", b"hello", |mut reader, mut writer| {
+            let mut buf = [0u8; 5];
+            // buggy: assumes the buffer was filled
+            reader.read(&mut buf).unwrap();
+            writer.write_all(&buf).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "(write side of the copy) failed at position 1"]
+    fn short_write() {
+        test_copy(b"hello", b"hello", |mut reader, mut writer| {
+            let mut buf = [0u8; 5];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: assumes the whole buffer was written
+            writer.write(&buf).unwrap();
+        });
+    }
+}
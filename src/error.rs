@@ -0,0 +1,610 @@
+//! Failure information shared by read and write testing.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::panic::resume_unwind;
+use std::sync::Once;
+
+use crate::backtrace_impl::{Backtrace, BacktraceStorageMut, DisplayBacktrace, DisplayTest, Operation, TestLocation};
+
+pub(crate) type Unwind = Box<dyn std::any::Any + Send + 'static>;
+
+/// Maps panic messages to their normalized form before they are compared.
+pub(crate) type NormalizeFn = fn(&str) -> String;
+
+/// Applies the normalization to the message, if any.
+pub(crate) fn normalize_message(message: Option<&str>, normalize: Option<NormalizeFn>) -> Option<Cow<'_, str>> {
+    match normalize {
+        Some(normalize) => message.map(|message| Cow::Owned(normalize(message))),
+        None => message.map(Cow::Borrowed),
+    }
+}
+
+thread_local! {
+    static QUIET_PANICS: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` without printing messages of panics happening on the current thread.
+///
+/// The search runs the closure many times and the messages of all the caught panics would flood
+/// the output before the actual report. The panic hook is global so instead of replacing it for
+/// the duration of the call, which would hide panics of other threads too (e.g. concurrently
+/// running tests), a wrapper is installed once and it skips the previous hook only on threads
+/// currently running inside this function.
+pub(crate) fn quiet_panics<T, F>(f: F) -> T where F: FnOnce() -> T {
+    static INSTALL_HOOK: Once = Once::new();
+
+    // restores the previous state even if `f` panics
+    struct Restore(bool);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            QUIET_PANICS.with(|quiet| quiet.set(self.0));
+        }
+    }
+
+    INSTALL_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !QUIET_PANICS.with(Cell::get) {
+                previous(info)
+            }
+        }));
+    });
+    let _restore = Restore(QUIET_PANICS.with(|quiet| quiet.replace(true)));
+    f()
+}
+
+/// Runs `attempt` for each split position until it fails.
+///
+/// Splits at zero and at the end are skipped since those are non-sensical.
+pub(crate) fn search<F>(len: usize, attempt: F) -> Option<Box<FailureInfo>> where F: FnMut(usize, BacktraceStorageMut<'_>) -> Result<(), Unwind> {
+    search_in(1..len, attempt)
+}
+
+/// Runs `attempt` for each of the given split positions until it fails.
+pub(crate) fn search_in<I, F>(positions: I, mut attempt: F) -> Option<Box<FailureInfo>> where I: IntoIterator<Item = usize>, F: FnMut(usize, BacktraceStorageMut<'_>) -> Result<(), Unwind> {
+    positions.into_iter().find_map(|pos| {
+        let mut backtrace = None;
+        let backtrace_mut = BacktraceStorageMut::from_mut(&mut backtrace);
+        attempt(pos, backtrace_mut)
+            .err()
+            .map(|unwind| {
+                crate::backtrace_impl::resolve(&mut backtrace);
+                Box::new(FailureInfo { unwind, pos, backtrace, last_read: None, also_reproduced: Vec::new(), stats: None, segment_boundary: None, breaking_pos: None, later_backtraces: Vec::new(), })
+            })
+    })
+}
+
+/// Checks whether the normalized messages of both panics are the same.
+pub(crate) fn same_message(a: &Unwind, b: &Unwind, normalize: Option<NormalizeFn>) -> bool {
+    normalize_message(get_panic_message(a), normalize) == normalize_message(get_panic_message(b), normalize)
+}
+
+/// Returns split positions of input with length `len` ordered by their distance from `start`.
+///
+/// Of two positions with the same distance the earlier one comes first.
+pub(crate) fn positions_around(len: usize, start: usize) -> impl Iterator<Item = usize> {
+    (0..len).flat_map(move |distance| {
+        let before = start.checked_sub(distance).filter(|pos| distance > 0 && *pos > 0);
+        let after = Some(start + distance).filter(|pos| *pos > 0 && *pos < len);
+        before.into_iter().chain(after)
+    })
+}
+
+/// Finds the smallest split position for which `attempt` returns `false` using multiple threads.
+///
+/// The positions are interleaved between the threads and each thread stops once it gets past a
+/// failing position found by any thread so the smallest one is found without trying all of them.
+#[cfg(feature = "parallel")]
+#[clippy::msrv = "1.63"]
+pub(crate) fn find_parallel<F>(len: usize, attempt: F) -> Option<usize> where F: Fn(usize) -> bool + Sync {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let found = AtomicUsize::new(usize::MAX);
+    std::thread::scope(|scope| {
+        for first in (1..len).take(threads) {
+            let attempt = &attempt;
+            let found = &found;
+            scope.spawn(move || quiet_panics(|| {
+                let mut pos = first;
+                while pos < len && pos < found.load(Ordering::Relaxed) {
+                    if !attempt(pos) {
+                        found.fetch_min(pos, Ordering::Relaxed);
+                        break;
+                    }
+                    pos += threads;
+                }
+            }));
+        }
+    });
+    match found.into_inner() {
+        usize::MAX => None,
+        pos => Some(pos),
+    }
+}
+
+pub(crate) struct FailureInfo {
+    pub(crate) unwind: Unwind,
+    pub(crate) pos: usize,
+    pub(crate) backtrace: Option<Backtrace>,
+    // the last `read` call in the failing run, only tracked when testing readers
+    pub(crate) last_read: Option<LastRead>,
+    // later positions at which the same failure reproduced, only searched for when testing readers
+    pub(crate) also_reproduced: Vec<usize>,
+    // all failing positions, only collected along with `also_reproduced`
+    pub(crate) stats: Option<FailureStats>,
+    // index of the segment boundary at the failing position when testing segmented input
+    pub(crate) segment_boundary: Option<usize>,
+    // later position reproducing the message of the breaking pass if it differs from the one at `pos`
+    pub(crate) breaking_pos: Option<usize>,
+    // backtraces of the failures at `stats.later_positions`, in the same order
+    pub(crate) later_backtraces: Vec<Option<Backtrace>>,
+}
+
+/// Maximum number of distinct panic messages tracked by `FailureStats`.
+const MAX_DISTINCT_MESSAGES: usize = 16;
+
+/// Number of failing split positions and distinct panic messages they produced.
+///
+/// Different messages suggest there are multiple bugs.
+#[derive(Debug)]
+pub(crate) struct FailureStats {
+    // failing positions after the reported one
+    pub(crate) later_positions: Vec<usize>,
+    // the first one is the message of the reported failure
+    messages: Vec<Option<String>>,
+    // there were more distinct messages than `MAX_DISTINCT_MESSAGES`
+    more_messages: bool,
+}
+
+impl FailureStats {
+    /// Creates the stats containing the reported failure.
+    pub(crate) fn new(message: Option<&str>) -> Self {
+        FailureStats {
+            later_positions: Vec::new(),
+            messages: vec![message.map(ToOwned::to_owned)],
+            more_messages: false,
+        }
+    }
+
+    /// Records a failure at another position, returns `true` if it's the same as the reported one.
+    pub(crate) fn record(&mut self, pos: usize, message: Option<&str>) -> bool {
+        self.later_positions.push(pos);
+        if message == self.messages[0].as_deref() {
+            return true;
+        }
+        if !self.messages.iter().any(|known| known.as_deref() == message) {
+            if self.messages.len() < MAX_DISTINCT_MESSAGES {
+                self.messages.push(message.map(ToOwned::to_owned));
+            } else {
+                self.more_messages = true;
+            }
+        }
+        false
+    }
+}
+
+impl fmt::Display for FailureStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} positions failed with ", self.later_positions.len() + 1)?;
+        if self.more_messages {
+            write!(f, "more than {} distinct messages", self.messages.len())
+        } else {
+            write!(f, "{} distinct messages", self.messages.len())
+        }
+    }
+}
+
+/// Buffer length passed to a `read` call and the number of bytes it returned.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct LastRead {
+    pub(crate) requested: usize,
+    pub(crate) returned: usize,
+}
+
+impl fmt::Display for LastRead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "requested {}, returned {}", self.requested, self.returned)
+    }
+}
+
+impl fmt::Debug for FailureInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FailureInfo")
+            .field("unwind", &format_args!("message: {:?}", get_panic_message(&self.unwind)))
+            .field("pos", &self.pos)
+            .field("last_read", &self.last_read)
+            .field("also_reproduced", &self.also_reproduced)
+            .field("stats", &self.stats)
+            .field("segment_boundary", &self.segment_boundary)
+            .field("breaking_pos", &self.breaking_pos)
+            .finish()
+    }
+}
+
+
+/// Test failure information
+pub(crate) struct Error {
+    pub(crate) unwind: Unwind,
+    // boxed to keep the error small, it's big compared to the rest
+    pub(crate) failure_info: Option<Box<FailureInfo>>,
+    pub(crate) operation: Operation,
+    pub(crate) location: TestLocation,
+    // size of the first chunk if the failure was found by sweeping it
+    pub(crate) first_chunk: Option<usize>,
+    // name of the last IO method called by the closure in the failing run, if tracked
+    pub(crate) last_method: Option<Method>,
+    // the last change of the called IO method in the failing run, if tracked
+    // boxed to keep the error small
+    pub(crate) transition: Option<Box<MethodTransition>>,
+    // shortest prefix of the input reproducing the failure, if shrinking was requested
+    pub(crate) shrunk_input: Option<Vec<u8>>,
+    // the failure was found by `test_copy`, `operation` tells which side of the copy misbehaved
+    pub(crate) copy_side: bool,
+    // makes messages differing only in volatile content compare equal
+    pub(crate) normalize_message: Option<NormalizeFn>,
+    // the failure was found with `write_all` accepting whole data
+    pub(crate) whole_write_all: bool,
+    // the failure happened when the passing closure was run again with whole writes
+    pub(crate) whole_rerun: bool,
+}
+
+/// A tracked IO method, displayed as its name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Method {
+    Read,
+    ReadExact,
+    ReadToEnd,
+    #[cfg(feature = "read_buf")]
+    ReadBuf,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Method::Read => "read",
+            Method::ReadExact => "read_exact",
+            Method::ReadToEnd => "read_to_end",
+            #[cfg(feature = "read_buf")]
+            Method::ReadBuf => "read_buf",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An IO method call, the length is unknown for methods that don't accept a buffer of fixed size.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct MethodCall {
+    pub(crate) name: Method,
+    pub(crate) len: Option<usize>,
+}
+
+impl fmt::Display for MethodCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.len {
+            Some(len) => write!(f, "{}({})", self.name, len),
+            None => write!(f, "{}(..)", self.name),
+        }
+    }
+}
+
+/// Change of the called IO method, bugs are often at such seams, e.g. a header read using
+/// `read_exact` followed by a body read using `read`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct MethodTransition {
+    pub(crate) from: MethodCall,
+    pub(crate) to: MethodCall,
+}
+
+impl fmt::Display for MethodTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} then {}", self.from, self.to)
+    }
+}
+
+/// Displays additional details about the failure, if known.
+struct DisplayDetails<'a> {
+    last_method: Option<Method>,
+    transition: Option<&'a MethodTransition>,
+    last_read: Option<LastRead>,
+    segment_boundary: Option<usize>,
+    also_reproduced: &'a [usize],
+    stats: Option<&'a FailureStats>,
+    shrunk_input: Option<&'a [u8]>,
+}
+
+impl fmt::Display for DisplayDetails<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(method) = self.last_method {
+            write!(f, "\nfailure after a `{}` call", method)?;
+        }
+        if let Some(transition) = self.transition {
+            write!(f, "\nmethod transition: {}", transition)?;
+        }
+        if let Some(read) = self.last_read {
+            write!(f, "\nlast read: {}", read)?;
+            // the most common bug this crate catches, so it's worth spelling out the fix
+            if self.last_method == Some(Method::Read) && read.requested > 1 && read.returned < read.requested {
+                write!(f, "\nhint: the failing `read` requested {} bytes but readers may return fewer; consider `read_exact`", read.requested)?;
+            }
+        }
+        if let Some(boundary) = self.segment_boundary {
+            write!(f, "\nthe position is the boundary between segments #{} and #{}", boundary, boundary + 1)?;
+        }
+        if !self.also_reproduced.is_empty() {
+            write!(f, "\nthe same failure also reproduced at later positions: {:?}", self.also_reproduced)?;
+        }
+        match self.stats {
+            Some(stats) if !stats.later_positions.is_empty() => write!(f, "\n{}", stats)?,
+            _ => (),
+        }
+        if let Some(input) = self.shrunk_input {
+            write!(f, "\nthe failure is reproducible with shorter input: {:?}", input)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// Creates the error of a failure without any additional information, which can be added by
+    /// setting the other fields.
+    pub(crate) fn new(unwind: Unwind, operation: Operation, location: TestLocation, normalize_message: Option<NormalizeFn>) -> Self {
+        Error {
+            unwind,
+            failure_info: None,
+            operation,
+            location,
+            first_chunk: None,
+            last_method: None,
+            transition: None,
+            shrunk_input: None,
+            copy_side: false,
+            normalize_message,
+            whole_write_all: false,
+            whole_rerun: false,
+        }
+    }
+
+    /// Resumes panic with relevant error information added if possible
+    pub(crate) fn panic(self) -> ! {
+        // there's nothing to add if neither the message nor the position is known
+        if self.failure_info.is_none() && get_panic_message(&self.unwind).is_none() {
+            resume_unwind(self.unwind);
+        }
+        panic!("{}", self)
+    }
+}
+
+/// Formats the message `panic` reports.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let first_panic_message = get_panic_message(&self.unwind);
+        let side = match (self.copy_side, &self.operation) {
+            (false, _) => "",
+            (true, Operation::Read) => " (read side of the copy)",
+            (true, Operation::Write) => " (write side of the copy)",
+        };
+        let variant = if self.whole_write_all { " (with `write_all` accepting whole data)" } else { "" };
+        let test = format!("{}{}{}", DisplayTest(self.location), side, variant);
+        let method = DisplayDetails {
+            last_method: self.last_method,
+            transition: self.transition.as_deref(),
+            last_read: self.failure_info.as_ref().and_then(|info| info.last_read),
+            segment_boundary: self.failure_info.as_ref().and_then(|info| info.segment_boundary),
+            also_reproduced: self.failure_info.as_ref().map_or(&[], |info| &info.also_reproduced),
+            stats: self.failure_info.as_ref().and_then(|info| info.stats.as_ref()),
+            shrunk_input: self.shrunk_input.as_deref(),
+        };
+        match (&self.failure_info, self.first_chunk) {
+            (Some(info), _) => {
+                let pos = info.pos;
+                let backtrace = DisplayBacktrace::new(&info.backtrace, self.operation);
+                match info.unwind.downcast_ref::<DecodedMismatch>() {
+                    Some(DecodedMismatch { index: Some(index), message, }) => return write!(f, "{} failed: value #{} mismatched at split pos {}: {}{}\n{}", test, index, pos, message, method, backtrace),
+                    Some(DecodedMismatch { index: None, message, }) => return write!(f, "{} failed: decoded mismatch at split pos {}: {}{}\n{}", test, pos, message, method, backtrace),
+                    None => (),
+                }
+                if let Some(EncoderMismatch { message, }) = info.unwind.downcast_ref() {
+                    return write!(f, "{} failed: encoder state mismatched at split pos {}: {}{}\n{}", test, pos, message, method, backtrace);
+                }
+                let second_panic_message = get_panic_message(&info.unwind);
+                match (first_panic_message, second_panic_message) {
+                    (Some(msg1), Some(msg2)) if normalize_message(Some(msg1), self.normalize_message) == normalize_message(Some(msg2), self.normalize_message) => write!(f, "{} failed at position {}: {}{}\n{}", test, pos, msg1, method, backtrace),
+                    (Some(msg1), Some(msg2)) => {
+                        write!(f, "{} failed with two different messages, likely caused by two bugs\nbug A localized at position {}: {}\n", test, pos, msg2)?;
+                        match info.breaking_pos {
+                            Some(breaking_pos) => write!(f, "bug B (breaking pass) localized at position {}: {}", breaking_pos, msg1)?,
+                            None => write!(f, "bug B (breaking pass) not localized: {}", msg1)?,
+                        }
+                        write!(f, "{}\n{}", method, backtrace)
+                    },
+                    (Some(msg), None) => write!(f, "{} failed with message \"{}\" but a different panic with unknown message was encountered at position {}{}\n{}", test, msg, pos, method, backtrace),
+                    (None, Some(msg)) => write!(f, "{} failed with unknown message but a different panic was encountered at position {}: {}{}\n{}", test, pos, msg, method, backtrace),
+                    (None, None) => write!(f, "{} failed at position {} with unknown messages{}\n{}", test, pos, method, backtrace),
+                }
+            },
+            (None, first_chunk) => {
+                if let Some(UnlocalizedFailure { message, }) = self.unwind.downcast_ref() {
+                    return write!(f, "{} failed: {}{}", test, message, method);
+                }
+                let msg = first_panic_message.unwrap_or("unknown message");
+                match first_chunk {
+                    Some(first_chunk) => {
+                        let chunk = match self.operation {
+                            Operation::Read => "first read returned",
+                            Operation::Write => "first write accepted",
+                        };
+                        write!(f, "{} failed when the {} {} bytes: {}{}", test, chunk, first_chunk, msg, method)
+                    },
+                    None if self.whole_rerun => {
+                        write!(f, "{} passed but failed when run again with whole writes: {}{}", test, msg, method)
+                    },
+                    None if self.last_method == Some(Method::ReadToEnd) && self.transition.is_none() => {
+                        write!(f, "{} failed: failure is not split-related (consumer used read_to_end): {}{}", test, msg, method)
+                    },
+                    None => write!(f, "{} failed but no split position reproduced the failure so it could not be localized, the closure may be non-deterministic or fail only if multiple calls are split: {}{}", test, msg, method),
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("unwind", &format_args!("message: {:?}", get_panic_message(&self.unwind)))
+            .field("failure_info", &self.failure_info)
+            .field("first_chunk", &self.first_chunk)
+            .field("last_method", &self.last_method)
+            .field("transition", &self.transition)
+            .field("shrunk_input", &self.shrunk_input)
+            .finish()
+    }
+}
+
+
+/// Failure detected by a test reader or writer smuggled through `io::Error`.
+///
+/// This allows closures returning `io::Result` to propagate the failure using `?` and the crate
+/// to distinguish it from genuine IO errors.
+#[derive(Debug)]
+pub(crate) struct SmuggledFailure(&'static str);
+
+impl fmt::Display for SmuggledFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for SmuggledFailure {}
+
+pub(crate) fn smuggle(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, SmuggledFailure(message))
+}
+
+/// Returns the failure if the error was created by `smuggle`.
+pub(crate) fn unsmuggle(error: &io::Error) -> Option<&SmuggledFailure> {
+    error.get_ref()?.downcast_ref()
+}
+
+/// Panic payload of `assert_read_eq!` and `test_read_iter` recognized when reporting the failure.
+pub(crate) struct DecodedMismatch {
+    // index of the mismatched value in a stream of values
+    pub(crate) index: Option<usize>,
+    pub(crate) message: String,
+}
+
+impl DecodedMismatch {
+    pub(crate) fn panic(self) -> ! {
+        // `panic_any` requires Rust 1.51, in edition 2018 `panic!` accepts any payload too
+        #[allow(unknown_lints, non_fmt_panics)]
+        {
+            panic!(self)
+        }
+    }
+}
+
+/// Failure of the whole test that isn't related to any split, e.g. a closure that never used the
+/// reader, reported as is instead of searching for a position.
+pub(crate) struct UnlocalizedFailure {
+    pub(crate) message: String,
+}
+
+impl UnlocalizedFailure {
+    pub(crate) fn unwind<M: Into<String>>(message: M) -> Unwind {
+        Box::new(UnlocalizedFailure { message: message.into(), })
+    }
+}
+
+/// Panic payload of `assert_write_eq!` recognized when reporting the failure.
+pub(crate) struct EncoderMismatch {
+    pub(crate) message: String,
+}
+
+impl EncoderMismatch {
+    pub(crate) fn panic(self) -> ! {
+        // see `DecodedMismatch::panic`
+        #[allow(unknown_lints, non_fmt_panics)]
+        {
+            panic!(self)
+        }
+    }
+}
+
+pub(crate) fn get_panic_message(unwind: &Unwind) -> Option<&str> {
+    match unwind.as_ref().downcast_ref::<&'static str>() {
+        Some(msg) => Some(*msg),
+        None => match unwind.as_ref().downcast_ref::<String>() {
+            Some(msg) => Some(msg.as_str()),
+            None => match unwind.as_ref().downcast_ref::<DecodedMismatch>() {
+                Some(mismatch) => Some(mismatch.message.as_str()),
+                None => match unwind.as_ref().downcast_ref::<EncoderMismatch>() {
+                    Some(mismatch) => Some(mismatch.message.as_str()),
+                    None => match unwind.as_ref().downcast_ref::<UnlocalizedFailure>() {
+                        Some(failure) => Some(failure.message.as_str()),
+                        // Copy what rustc does in the default panic handler
+                        None => None,
+                    },
+                },
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+impl Error {
+    pub(crate) fn panic_message1(&self) -> Option<&str> {
+        get_panic_message(&self.unwind)
+    }
+
+    pub(crate) fn panic_message2(&self) -> Option<&str> {
+        self.failure_info.as_ref().and_then(|info| get_panic_message(&info.unwind))
+    }
+
+    pub(crate) fn pos(&self) -> Option<usize> {
+        self.failure_info.as_ref().map(|info| info.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::panic::catch_unwind;
+    use super::{positions_around, quiet_panics, FailureStats, MAX_DISTINCT_MESSAGES, QUIET_PANICS};
+
+    #[test]
+    fn positions_around_start() {
+        assert_eq!(positions_around(6, 2).collect::<Vec<_>>(), [2, 1, 3, 4, 5]);
+        assert_eq!(positions_around(6, 5).collect::<Vec<_>>(), [5, 4, 3, 2, 1]);
+        assert_eq!(positions_around(2, 1).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn quiet_panics_restores_state() {
+        assert!(!QUIET_PANICS.with(Cell::get));
+        let result = quiet_panics(|| {
+            assert!(QUIET_PANICS.with(Cell::get));
+            catch_unwind(|| quiet_panics(|| panic!("quiet"))).unwrap_err();
+            QUIET_PANICS.with(Cell::get)
+        });
+        assert!(result);
+        assert!(!QUIET_PANICS.with(Cell::get));
+    }
+
+    #[test]
+    fn failure_stats_are_capped() {
+        let mut stats = FailureStats::new(Some("primary"));
+        assert!(stats.record(2, Some("primary")));
+        assert!(!stats.record(3, None));
+        assert!(!stats.record(4, None));
+        assert_eq!(stats.to_string(), "4 positions failed with 2 distinct messages");
+        for i in 0..MAX_DISTINCT_MESSAGES {
+            stats.record(5 + i, Some(&i.to_string()));
+        }
+        assert_eq!(stats.to_string(), format!("{} positions failed with more than {} distinct messages", MAX_DISTINCT_MESSAGES + 4, MAX_DISTINCT_MESSAGES));
+    }
+}
@@ -0,0 +1,227 @@
+//! Small formatters used to display data.
+
+use std::fmt;
+
+/// Number of bytes displayed in a single row of each side.
+const ROW_LEN: usize = 8;
+
+/// Number of rows displayed before and after the row containing the first difference.
+const CONTEXT_ROWS: usize = 2;
+
+/// Displays expected and received bytes side-by-side, highlighting the first difference.
+///
+/// The output resembles `hexdump -C` with two columns - expected on the left, received on the
+/// right. A caret is placed under the first differing byte in both columns. Only a few rows
+/// around the difference are displayed so that huge buffers don't flood the output.
+pub(crate) struct HexDiff<'a> {
+    expected: &'a [u8],
+    received: &'a [u8],
+    // offset of the first byte in the stream, used for labeling the rows
+    base: usize,
+}
+
+impl<'a> HexDiff<'a> {
+    pub(crate) fn new(expected: &'a [u8], received: &'a [u8], base: usize) -> Self {
+        HexDiff {
+            expected,
+            received,
+            base,
+        }
+    }
+
+    fn first_difference(&self) -> Option<usize> {
+        self.expected
+            .iter()
+            .zip(self.received)
+            .position(|(expected, received)| expected != received)
+            .or_else(|| {
+                if self.expected.len() != self.received.len() {
+                    Some(self.expected.len().min(self.received.len()))
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+fn write_side(f: &mut fmt::Formatter, data: &[u8], row: usize) -> fmt::Result {
+    let start = (row * ROW_LEN).min(data.len());
+    let end = ((row + 1) * ROW_LEN).min(data.len());
+    let bytes = &data[start..end];
+    for byte in bytes {
+        write!(f, "{:02x} ", byte)?;
+    }
+    for _ in bytes.len()..ROW_LEN {
+        write!(f, "   ")?;
+    }
+    write!(f, " |")?;
+    for byte in bytes {
+        let c = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' };
+        write!(f, "{}", c)?;
+    }
+    for _ in bytes.len()..ROW_LEN {
+        write!(f, " ")?;
+    }
+    write!(f, "|")
+}
+
+impl fmt::Display for HexDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let diff = match self.first_difference() {
+            Some(diff) => diff,
+            None => return write!(f, "no difference"),
+        };
+        writeln!(f, "first difference at offset {}", self.base + diff)?;
+
+        let len = self.expected.len().max(self.received.len());
+        let rows = len / ROW_LEN + (len % ROW_LEN != 0) as usize;
+        let diff_row = diff / ROW_LEN;
+        let first_row = diff_row.saturating_sub(CONTEXT_ROWS);
+        let end_row = (diff_row + CONTEXT_ROWS + 1).min(rows);
+        // offset + bytes + ascii
+        let side_width = 3 * ROW_LEN + ROW_LEN + 3;
+
+        writeln!(f, "{:8}  {:<width$}    received", "", "expected", width = side_width)?;
+        if first_row > 0 {
+            writeln!(f, "...")?;
+        }
+        for row in first_row..end_row {
+            write!(f, "{:08x}  ", self.base + row * ROW_LEN)?;
+            write_side(f, self.expected, row)?;
+            write!(f, "    ")?;
+            write_side(f, self.received, row)?;
+            writeln!(f)?;
+            if row == diff_row {
+                let caret_offset = 3 * (diff % ROW_LEN);
+                write!(f, "{:8}  {:caret_offset$}^^", "", "", caret_offset = caret_offset)?;
+                write!(f, "{:width$}^^", "", width = side_width + 4 - 2)?;
+                writeln!(f)?;
+            }
+        }
+        if end_row < rows {
+            writeln!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of lines displayed by `DebugDiff` before the first differing line.
+const CONTEXT_LINES: usize = 2;
+
+/// Displays the first differing line of pretty `Debug` outputs of two values.
+///
+/// Multi-line values, e.g. large structs, are hard to compare when printed whole, this shows
+/// only the differing line with a few lines above it, which usually name the containing field.
+pub(crate) struct DebugDiff<'a> {
+    expected: &'a str,
+    received: &'a str,
+}
+
+impl<'a> DebugDiff<'a> {
+    pub(crate) fn new(expected: &'a str, received: &'a str) -> Self {
+        DebugDiff {
+            expected,
+            received,
+        }
+    }
+}
+
+impl fmt::Display for DebugDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected = self.expected.lines().collect::<Vec<_>>();
+        let received = self.received.lines().collect::<Vec<_>>();
+        let diff = expected.iter().zip(&received).position(|(expected, received)| expected != received);
+        let diff = match diff {
+            Some(diff) => diff,
+            None if expected.len() != received.len() => expected.len().min(received.len()),
+            None => return write!(f, "the debug outputs are equal, the values differ in a way `Debug` doesn't show"),
+        };
+        writeln!(f, "first difference at line {} of the debug output (- expected, + received):", diff + 1)?;
+        for line in &expected[diff.saturating_sub(CONTEXT_LINES)..diff] {
+            writeln!(f, " {}", line)?;
+        }
+        writeln!(f, "-{}", expected.get(diff).unwrap_or(&"<end>"))?;
+        write!(f, "+{}", received.get(diff).unwrap_or(&"<end>"))
+    }
+}
+
+/// Number of bytes displayed by `Truncated`.
+pub(crate) const TRUNCATED_LEN: usize = 8;
+
+/// Debug-formats at most a few bytes so that huge buffers don't flood the output.
+pub(crate) struct Truncated<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for Truncated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut list = f.debug_list();
+        list.entries(self.0.iter().take(TRUNCATED_LEN));
+        if self.0.len() > TRUNCATED_LEN {
+            list.entry(&format_args!(".."));
+        }
+        list.finish()
+    }
+}
+
+/// Debug-formats at most a few last bytes, the counterpart of `Truncated`.
+pub(crate) struct TruncatedTail<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for TruncatedTail<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut list = f.debug_list();
+        if self.0.len() > TRUNCATED_LEN {
+            list.entry(&format_args!(".."));
+        }
+        list.entries(&self.0[self.0.len().saturating_sub(TRUNCATED_LEN)..]);
+        list.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugDiff, HexDiff, Truncated, TruncatedTail};
+
+    #[test]
+    fn caret_under_first_difference() {
+        let diff = HexDiff::new(b"abcdefgh", b"abcXefgh", 0).to_string();
+        let mut lines = diff.lines();
+        assert_eq!(lines.next(), Some("first difference at offset 3"));
+        lines.next();
+        assert_eq!(lines.next(), Some("00000000  61 62 63 64 65 66 67 68  |abcdefgh|    61 62 63 58 65 66 67 68  |abcXefgh|"));
+        assert_eq!(lines.next(), Some("                   ^^                                     ^^"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn only_context_is_displayed() {
+        let expected = [0u8; 64];
+        let mut received = [0u8; 64];
+        received[30] = 1;
+        let diff = HexDiff::new(&expected, &received, 100).to_string();
+        assert!(diff.starts_with("first difference at offset 130\n"));
+        assert_eq!(diff.lines().filter(|line| *line == "...").count(), 2);
+        assert!(diff.contains("\n0000007c  "));
+        assert!(!diff.contains("\n00000064  "));
+    }
+
+    #[test]
+    fn debug_diff() {
+        let diff = DebugDiff::new("A {\n    x: 1,\n    y: 2,\n    z: 3,\n}", "A {\n    x: 1,\n    y: 5,\n    z: 3,\n}").to_string();
+        assert_eq!(diff, "first difference at line 3 of the debug output (- expected, + received):\n A {\n     x: 1,\n-    y: 2,\n+    y: 5,");
+        let diff = DebugDiff::new("[\n    1,\n]", "[\n    1,\n    2,\n]").to_string();
+        assert!(diff.ends_with("\n-]\n+    2,"), "{}", diff);
+        let diff = DebugDiff::new("[\n    1,\n]", "[\n    1,\n]\nextra").to_string();
+        assert!(diff.ends_with("\n-<end>\n+extra"), "{}", diff);
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(format!("{:?}", Truncated(&[1, 2])), "[1, 2]");
+        assert_eq!(format!("{:?}", Truncated(&[0; 9])), "[0, 0, 0, 0, 0, 0, 0, 0, ..]");
+    }
+
+    #[test]
+    fn truncated_tail() {
+        assert_eq!(format!("{:?}", TruncatedTail(&[1, 2])), "[1, 2]");
+        assert_eq!(format!("{:?}", TruncatedTail(&[0, 1, 2, 3, 4, 5, 6, 7, 8])), "[.., 1, 2, 3, 4, 5, 6, 7, 8]");
+    }
+}
@@ -17,13 +17,30 @@ You should then compare the decoded value to the expected value and *panic* if t
 Similarly, `test_write` accepts the expected bytes as an argument and a closure implementing the test.
 The closure accepts a writer has to write data into it.
 The written data is internally compared to the expected.
+If you implement `Read` on top of another reader (e.g. a decompressor) use `test_reader_impl` to check that it handles splits of its source.
+Similarly, `test_writer_impl` checks that a `Write` implementation wrapping another writer handles its partial writes.
+Code moving data from a reader to a writer (e.g. using `io::copy`) can be tested by `test_copy` which checks both sides.
+Consumers of `BufRead` can be tested by `test_bufread` which limits the number of bytes returned by `fill_buf`.
+If the search takes too long for large inputs enable `parallel` feature and use `ReadTest::run_parallel` to search on multiple threads.
+To see exactly which IO calls your code made install a tracer using `ReadTest::trace` or `WriteTest::trace`.
 
 If your code has a bug caused by improper handling of splits this crate will find it and even find the exact incorrectly-handled call.
 Finding the culprit requires `backtrace` feature which is on by default.
+If `rust_1_46` is enabled as well the location of the culprit is obtained using `#[track_caller]` which is more precise than guessing it from the backtrace.
+If you'd rather avoid the dependency you can use `std_backtrace` feature instead which uses `std::backtrace::Backtrace`.
+Its output is controlled by `RUST_BACKTRACE` and `RUST_LIB_BACKTRACE` as usual.
+When `RUST_BACKTRACE=1` is set the full backtrace is printed as well.
+To keep the output readable (e.g. in CI logs) you can set `IO_CHECK_BACKTRACE_FRAMES` to a number of frames that should be printed starting with the culprit.
+When running under Miri backtraces are never captured regardless of the features because it'd be extremely slow.
+The search still works but the reports don't contain the culprit, so Miri runs trade diagnostics for speed.
 
 Keep in mind that if there are multiple such bugs the crate only finds one at a time - the one corresponding to the leftmost part of the input.
 Once you fix it you can re-run the test and it will report the next bug.
 Repeat this until you fix all of them.
+To reproduce a failure of a read test at a reported position (e.g. from a CI log) without running the search set `IO_CHECK_FORCE_POS` to that position.
+The closure is then only run with the input split there.
+The variable affects every read test in the process, so combine it with a test name filter, e.g. `IO_CHECK_FORCE_POS=3 cargo test decodes_header`.
+Tests with inputs too short to be split at that position fall back to the normal search, which is reported by a warning printed once.
 
 Note that this crate should be normally used as a dev-dependency only.
 
@@ -47,25 +64,43 @@ If the closure panics the captured backtrace is used in error reporting.
 
 ### Write
 
-As opposed to read, write can detect bugs on-the-fly so it will find the bug and probable location a bit sooner.
-However it can report a wrong location, so the plan is to make it more similar to read.
-See issue [#1](https://github.com/Kixunil/io_check/issues/1) for more information.
+Write testing works very similarly.
+The closure is called first with a writer that accepts a single byte in each `write` call and checks the written data on-the-fly.
+If the written data doesn't match or the closure doesn't write all the expected bytes the writer panics.
 
-Other than that it is very similar - splits writes at each position and if written data don't match it reports an error.
-Backtrace capturing is similar as well - the last call is captured and if the current call writes unexpected data last one is reported as likely culprit.
+The panic is caught and search is run to find the exact place where the problem occurs.
+The closure gets called multiple times with another writer that accepts whole writes except the one crossing the split position.
+That one is shortened so that it ends exactly at the split position and its backtrace is captured.
+Once a call panics the captured backtrace is used in error reporting, just like in the case of read.
 
 ## MSRV
 
 1.41.1 without `backtrace` feature, 1.48 with `backtrace` feature.
+`rust_1_46` feature requires 1.46, `parallel` feature requires 1.63 and `std_backtrace` feature requires 1.65.
 
 ## License
 
 MITNFA
 "]
 
-pub use read::hack::test_read;
-pub use write::hack::test_write;
+#![cfg_attr(feature = "read_buf", feature(read_buf, core_io_borrowed_buf))]
 
+pub use read::hack::{sanity_read, test_read, test_read_check, test_read_eq, test_read_errors, test_read_fallible, test_read_gen, test_read_iter, test_read_schedule, test_read_segments, test_read_short, test_reader_impl, try_test_read};
+pub use write::hack::{sanity_write, test_write, test_write_capacity, test_write_fallible, test_write_requires_flush, test_write_with, test_writer_impl, try_test_write, try_test_write_summary};
+pub use bufread::test_bufread;
+pub use copy::test_copy;
+
+pub mod bufread;
 pub mod read;
+pub mod report;
+pub mod trace;
 pub mod write;
 mod backtrace_impl;
+mod copy;
+mod error;
+mod hex;
+mod macros;
+mod split;
+
+#[doc(hidden)]
+pub use macros::__private;
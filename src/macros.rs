@@ -0,0 +1,275 @@
+//! Convenience macros for the common case of comparing decoded/encoded values.
+
+/// Tests that decoding `input` results in `expected` value.
+///
+/// This is a shorthand for calling [`test_read`](crate::test_read) with a closure that decodes
+/// the value using `decode` and asserts that it equals `expected`. `decode` has to be a closure
+/// accepting [`TestReader`](crate::read::TestReader) and returning `io::Result<T>`. Errors are
+/// reported along with the location of the macro call.
+///
+/// `expected` is evaluated once and `T` has to implement `PartialEq` and `Debug`.
+///
+/// ## Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// fn decode<R: Read>(mut reader: R) -> std::io::Result<u16> {
+///     let mut buf = [0; 2];
+///     reader.read_exact(&mut buf)?;
+///     Ok(u16::from_le_bytes(buf))
+/// }
+///
+/// io_check::check_read!([42, 0], 42, |reader| decode(reader));
+/// ```
+#[macro_export]
+macro_rules! check_read {
+    ($input:expr, $expected:expr, $decode:expr $(,)?) => {{
+        let expected = $expected;
+        let decode = $crate::__private::read_decoder($decode);
+        $crate::test_read(&$input, |reader| {
+            match decode(reader) {
+                Ok(value) => assert_eq!(value, expected),
+                Err(error) => panic!("decoding failed at {}:{}: {}", file!(), line!(), error),
+            }
+        })
+    }};
+}
+
+/// Tests that encoding into the writer results in `expected` bytes.
+///
+/// This is a shorthand for calling [`test_write`](crate::test_write) with a closure that encodes
+/// the value using `encode`. `encode` has to be a closure accepting
+/// [`TestWriter`](crate::write::TestWriter) and returning `io::Result<()>`. Errors are reported
+/// along with the location of the macro call.
+///
+/// ## Example
+///
+/// ```
+/// use std::io::Write;
+///
+/// fn encode<W: Write>(value: u16, mut writer: W) -> std::io::Result<()> {
+///     writer.write_all(&value.to_le_bytes())
+/// }
+///
+/// io_check::check_write!([42, 0], |writer| encode(42, writer));
+/// ```
+#[macro_export]
+macro_rules! check_write {
+    ($expected:expr, $encode:expr $(,)?) => {{
+        let encode = $crate::__private::write_encoder($encode);
+        $crate::test_write(&$expected, |writer| {
+            if let Err(error) = encode(writer) {
+                panic!("encoding failed at {}:{}: {}", file!(), line!(), error);
+            }
+        })
+    }};
+}
+
+/// Asserts that the decoded value equals the expected one inside a read test closure.
+///
+/// This behaves like `assert_eq!` but the failure is recognized by this crate and reported as a
+/// decoded mismatch along with the split position at which it happened and the location of the
+/// culprit. Outside of read tests the panic message is not displayed by the default panic hook
+/// so `assert_eq!` should be used there instead.
+///
+/// ## Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// io_check::test_read(&[42, 0], |mut reader| {
+///     let mut buf = [0; 2];
+///     reader.read_exact(&mut buf).unwrap();
+///     io_check::assert_read_eq!(u16::from_le_bytes(buf), 42);
+/// });
+/// ```
+#[macro_export]
+macro_rules! assert_read_eq {
+    ($got:expr, $want:expr $(,)?) => {
+        match (&$got, &$want) {
+            (got, want) => if !(*got == *want) {
+                $crate::__private::decoded_mismatch(got, want, file!(), line!())
+            },
+        }
+    };
+}
+
+/// Asserts that a value equals the expected one inside a write test closure.
+///
+/// This is the write counterpart of [`assert_read_eq!`] for checking intermediate state of the
+/// encoder, e.g. the number of bytes it thinks it wrote. The failure is recognized by this crate
+/// and reported along with the split position at which it happened and the location of the
+/// culprit. Just like with `assert_read_eq!` the panic message is not displayed by the default
+/// panic hook outside of write tests.
+///
+/// ## Example
+///
+/// ```
+/// use std::io::Write;
+///
+/// io_check::test_write(&[42, 0, 1], |mut writer| {
+///     writer.write_all(&[42, 0]).unwrap();
+///     io_check::assert_write_eq!(writer.remaining(), 1);
+///     writer.write_all(&[1]).unwrap();
+/// });
+/// ```
+#[macro_export]
+macro_rules! assert_write_eq {
+    ($got:expr, $want:expr $(,)?) => {
+        match (&$got, &$want) {
+            (got, want) => if !(*got == *want) {
+                $crate::__private::encoder_mismatch(got, want, file!(), line!())
+            },
+        }
+    };
+}
+
+/// Items used by macros, not public API.
+#[doc(hidden)]
+pub mod __private {
+    use std::fmt;
+    use std::io;
+    use crate::error::{DecodedMismatch, EncoderMismatch};
+    use crate::read::TestReader;
+    use crate::write::TestWriter;
+
+    /// Panics with the payload of `assert_read_eq!`.
+    #[cold]
+    pub fn decoded_mismatch(got: &dyn fmt::Debug, want: &dyn fmt::Debug, file: &'static str, line: u32) -> ! {
+        let message = format!("decoded {:?} but expected {:?} (assert_read_eq! at {}:{})", got, want, file, line);
+        DecodedMismatch { index: None, message, }.panic()
+    }
+
+    /// Panics with the payload of `assert_write_eq!`.
+    #[cold]
+    pub fn encoder_mismatch(got: &dyn fmt::Debug, want: &dyn fmt::Debug, file: &'static str, line: u32) -> ! {
+        let message = format!("got {:?} but expected {:?} (assert_write_eq! at {}:{})", got, want, file, line);
+        EncoderMismatch { message, }.panic()
+    }
+
+    /// Helps type inference of the closure passed to `check_read!`.
+    pub fn read_decoder<T, F>(decode: F) -> F where F: Fn(TestReader<'_>) -> io::Result<T> {
+        decode
+    }
+
+    /// Helps type inference of the closure passed to `check_write!`.
+    pub fn write_encoder<F>(encode: F) -> F where F: Fn(TestWriter<'_>) -> io::Result<()> {
+        encode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::{self, Read, Write};
+
+    fn decode<R: Read>(mut reader: R) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    #[test]
+    fn read() {
+        check_read!([42, 0], 42, |reader| decode(reader));
+    }
+
+    #[test]
+    fn read_method_calls_in_closure() {
+        check_read!([1u8, 2], [1u8, 2], |mut reader| {
+            let mut buf = [0; 2];
+            reader.read_exact(&mut buf).map(|_| buf)
+        });
+    }
+
+    #[test]
+    fn read_hygiene() {
+        let expected = 1u16;
+        let reader = 42u16;
+        let evaluations = Cell::new(0);
+        let eval = || { evaluations.set(evaluations.get() + 1); reader };
+        check_read!([42, 0], eval(), |reader| decode(reader));
+        assert_eq!(expected, 1);
+        assert_eq!(evaluations.get(), 1);
+    }
+
+    #[test]
+    #[should_panic = "decoding failed at src/macros.rs:"]
+    fn read_error() {
+        check_read!([42, 0], 42u32, |mut reader| {
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf).map(|_| u32::from_le_bytes(buf))
+        });
+    }
+
+    #[test]
+    fn assert_read_eq_passes() {
+        crate::test_read(&[42, 0], |reader| {
+            assert_read_eq!(decode(reader).unwrap(), 42);
+        });
+    }
+
+    #[test]
+    #[should_panic = "decoded mismatch at split pos 1: decoded"]
+    // the test intentionally contains buggy IO code
+    #[allow(clippy::unused_io_amount)]
+    fn assert_read_eq_mismatch() {
+        crate::test_read(&[42, 0], |mut reader| {
+            let mut buf = [0; 2];
+            reader.read(&mut buf).unwrap();
+            assert_read_eq!(u16::from_le_bytes(buf), 42);
+        });
+    }
+
+    #[test]
+    fn assert_read_eq_message() {
+        let failure = crate::try_test_read(&[42, 0], |mut reader| {
+            let mut buf = [0; 2];
+            reader.read_exact(&mut buf).unwrap();
+            assert_read_eq!(u16::from_le_bytes(buf), 7u16);
+        }).unwrap_err();
+        let message = failure.message().unwrap();
+        assert!(message.starts_with("decoded 42 but expected 7 (assert_read_eq! at src/macros.rs:"), "{}", message);
+    }
+
+    #[test]
+    fn write() {
+        check_write!([42, 0], |mut writer| writer.write_all(&42u16.to_le_bytes()));
+    }
+
+    #[test]
+    fn write_hygiene() {
+        let writer = 42u16;
+        let encode = 0;
+        check_write!([42, 0], |mut w| w.write_all(&writer.to_le_bytes()));
+        assert_eq!(encode, 0);
+    }
+
+    #[test]
+    #[should_panic = "encoding failed at src/macros.rs:"]
+    fn write_error() {
+        check_write!([42, 0], |_| Err(io::Error::new(io::ErrorKind::Other, "failed")));
+    }
+
+    #[test]
+    #[should_panic = "encoder state mismatched at split pos 1: got 1 but expected 0 (assert_write_eq! at src/macros.rs:"]
+    // the test intentionally contains buggy IO code
+    #[allow(clippy::unused_io_amount)]
+    fn assert_write_eq_mismatch() {
+        crate::test_write(&[42, 0], |mut writer| {
+            writer.write(&[42, 0]).unwrap();
+            assert_write_eq!(writer.remaining(), 0);
+        });
+    }
+
+    #[test]
+    fn assert_write_eq_message() {
+        let failure = crate::try_test_write(&[42, 0], |mut writer| {
+            writer.write_all(&[42, 0]).unwrap();
+            assert_write_eq!(writer.remaining(), 7);
+        }).unwrap_err();
+        let message = failure.message().unwrap();
+        assert!(message.starts_with("got 0 but expected 7 (assert_write_eq! at src/macros.rs:"), "{}", message);
+    }
+}
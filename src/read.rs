@@ -1,11 +1,20 @@
 //! Contains items related to testing of `Read` usage.
 
-use std::io::{self, Read};
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::fmt;
-use std::panic::{catch_unwind, resume_unwind, UnwindSafe, RefUnwindSafe};
-use either::Either;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe, UnwindSafe, RefUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex, Once};
+use std::time::Duration;
 
-use crate::backtrace_impl::{Backtrace, BacktraceStorageMut, DisplayBacktrace};
+use crate::backtrace_impl::{self, BacktraceStorageMut, DisplayBacktrace, DisplayTest, Operation, TestLocation};
+use crate::error::{self, DecodedMismatch, Error, FailureStats, LastRead, Method, MethodCall, MethodTransition, NormalizeFn, UnlocalizedFailure};
+use crate::hex::{DebugDiff, HexDiff, Truncated, TRUNCATED_LEN};
+use crate::report::{PanicReporter, Reporter};
+use crate::split;
+use crate::trace::{IoEvent, TraceFn};
 
 /// Reader that splits input the to test `Read` consumers.
 ///
@@ -16,277 +25,3647 @@ use crate::backtrace_impl::{Backtrace, BacktraceStorageMut, DisplayBacktrace};
 /// Currently the readers splits the input at each byte and, if the closure panics, it splits the
 /// input in two to find the position where the problem occurs.
 ///
+/// Consuming the reader using [`Read::bytes`] is supported. Since each byte is read separately
+/// the reads are never split, so failures of such consumers are reported as unrelated to
+/// splitting.
+///
+/// The reader behaves the same when used through `&mut dyn Read`: the overridden `read_exact`
+/// and `read_to_end` are part of the trait object so they are not split (unless
+/// [`ReadTest::sabotage_all`] is enabled) regardless of whether the consumer is generic or takes
+/// a trait object.
+///
 /// [`test_read`]: super::test_read
-pub struct TestReader<'a>(Either<BreakingReader<'a>, SearchingReader<'a>>);
+pub struct TestReader<'a> {
+    reader: SplitReader<'a>,
+    config: &'a Config,
+    // `None` for standalone readers
+    last_method: Option<&'a LastMethod>,
+    // number of consecutive reads that returned 0
+    eof_reads: usize,
+    // the previous `read` call and the number of consecutive calls shrinking faster than the
+    // returned data, used by `ReadTest::detect_shrinking_reads`
+    previous_read: Option<LastRead>,
+    shrinking_reads: usize,
+    // set once a read returned EOF, after that the reader never returns data again
+    at_eof: bool,
+    // the whole input, used to reposition the reader when seeking
+    input: Input<'a>,
+    // error returned instead of data once, used by `ReadTest::run_errors`
+    injection: Option<&'a Injection>,
+}
 
 impl<'a> TestReader<'a> {
-    fn breaking(input: &'a [u8]) -> Self {
-        TestReader(Either::Left(BreakingReader(input)))
+    fn breaking(input: Input<'a>, config: &'a Config, last_method: &'a LastMethod) -> Self {
+        Self::greedy(input, config, last_method, 0)
     }
 
-    fn searching(input: &'a [u8], pos: usize, backtrace: BacktraceStorageMut<'a>) -> Self {
-        TestReader(Either::Right(SearchingReader::new(input, pos, backtrace)))
+    /// Returns the first `greedy` bytes at once (as much as the buffer allows), then breaks.
+    fn greedy(input: Input<'a>, config: &'a Config, last_method: &'a LastMethod, greedy: usize) -> Self {
+        config.trace(IoEvent::Run { split: None, });
+        TestReader {
+            reader: SplitReader::Breaking(BreakingReader { input, pos: 0, greedy, schedule: &[], splits: &[], config, tracked: false, split_count: Some(&last_method.splits), }),
+            config,
+            last_method: Some(last_method),
+            eof_reads: 0,
+            previous_read: None,
+            shrinking_reads: 0,
+            at_eof: false,
+            input,
+            injection: None,
+        }
     }
-}
 
-impl Read for TestReader<'_> {
+    /// Returns chunks of sizes given by `schedule`, then breaks.
+    fn scheduled(input: Input<'a>, config: &'a Config, last_method: &'a LastMethod, schedule: &'a [usize]) -> Self {
+        let mut reader = Self::breaking(input, config, last_method);
+        if let SplitReader::Breaking(breaking) = &mut reader.reader {
+            breaking.schedule = schedule;
+        }
+        reader
+    }
+
+    /// Reads byte-by-byte, returning the injected error once the position is reached.
+    fn injecting(input: Input<'a>, config: &'a Config, last_method: &'a LastMethod, injection: &'a Injection) -> Self {
+        let mut reader = Self::breaking(input, config, last_method);
+        reader.injection = Some(injection);
+        reader
+    }
+
+    fn searching(input: Input<'a>, config: &'a Config, last_method: &'a LastMethod, pos: usize, backtrace: BacktraceStorageMut<'a>) -> Self {
+        config.trace(IoEvent::Run { split: Some(pos), });
+        TestReader {
+            reader: SplitReader::Searching(SearchingReader { input, pos: 0, split: pos, backtrace, config, tracked: false, }),
+            config,
+            last_method: Some(last_method),
+            eof_reads: 0,
+            previous_read: None,
+            shrinking_reads: 0,
+            at_eof: false,
+            input,
+            injection: None,
+        }
+    }
+
+    /// Creates a standalone reader returning `input` split at the given positions.
+    ///
+    /// No read returns data crossing any of the positions, otherwise reads return as much data as
+    /// the buffer allows. Just like in tests, the byte of the buffer right after the returned data
+    /// is poisoned. This is useful for demonstrating how a consumer handles particular splits,
+    /// e.g. in doctests, without running the search of [`test_read`](crate::test_read).
+    ///
+    /// Panics if the positions are not strictly increasing or not within the input.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use io_check::read::TestReader;
+    ///
+    /// let mut reader = TestReader::with_splits(b"hello world", &[5]);
+    /// let mut buf = [0u8; 11];
+    /// assert_eq!(reader.read(&mut buf).unwrap(), 5);
+    /// assert_eq!(reader.read(&mut buf[5..]).unwrap(), 6);
+    /// assert_eq!(&buf, b"hello world");
+    /// ```
+    pub fn with_splits(input: &'a [u8], splits: &'a [usize]) -> Self {
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]), "split positions {:?} are not strictly increasing", splits);
+        assert!(splits.iter().all(|split| *split > 0 && *split < input.len()), "split positions {:?} must be between 1 and {}", splits, input.len().saturating_sub(1));
+        let input = Input::Slice(input);
+        let config = &DEFAULT_CONFIG;
+        TestReader {
+            reader: SplitReader::Breaking(BreakingReader { input, pos: 0, greedy: std::usize::MAX, schedule: &[], splits, config, tracked: false, split_count: None, }),
+            config,
+            last_method: None,
+            eof_reads: 0,
+            previous_read: None,
+            shrinking_reads: 0,
+            at_eof: false,
+            input,
+            injection: None,
+        }
+    }
+
+    /// Reads into the tracked buffer without poisoning it.
+    ///
+    /// This behaves like [`read`](Read::read) except that the byte after the returned data is not
+    /// poisoned. Instead the consumer reports how many bytes it used by calling
+    /// [`TrackedBuf::consume`], which fails if it used more than the read returned. This allows
+    /// testing parsers that peek by reading into a large buffer and decoding it speculatively,
+    /// which would trip over the poisoned byte even if they correctly use only the returned data
+    /// in the end.
     #[cfg_attr(feature = "rust_1_46", track_caller)]
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        // `read_exact` doesn't have `#[track_caller]`, so we have to bypass it
-        // I don't care to find which version of `either` supports `for_both!` and supporting all
-        // versions is nice.
-        match &mut self.0 {
-            Either::Left(reader) => reader.read(buf),
-            Either::Right(reader) => reader.read(buf),
+    pub fn read_tracked(&mut self, buf: &mut TrackedBuf<'_>) -> io::Result<usize> {
+        self.reader.set_tracked(true);
+        let result = self.read(buf.buf);
+        self.reader.set_tracked(false);
+        let read = result?;
+        buf.filled = read;
+        buf.consumed = 0;
+        Ok(read)
+    }
+
+    /// Fails if too many consecutive reads requested less than the previous one minus the data it
+    /// returned.
+    fn check_shrinking(&mut self, read: LastRead) -> io::Result<()> {
+        match self.previous_read.replace(read) {
+            Some(previous) if read.requested + previous.returned < previous.requested => {
+                self.shrinking_reads += 1;
+                if self.shrinking_reads >= MAX_SHRINKING_READS {
+                    return Err(self.fail("consumer requested progressively shrinking buffers, the remaining length is likely computed wrong"));
+                }
+            },
+            _ => self.shrinking_reads = 0,
         }
+        Ok(())
     }
 
-    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
-        // `read_exact` is not overridden in `Either`, so we have to do it ourselves
-        // I don't care to find which version of `either` supports `for_both!` and supporting all
-        // versions is nice.
-        match &mut self.0 {
-            Either::Left(reader) => reader.read_exact(buf),
-            Either::Right(reader) => reader.read_exact(buf),
+    /// Records the called method if tracked.
+    fn track(&self, name: Method, len: Option<usize>) {
+        if let Some(last_method) = self.last_method {
+            last_method.set(name, len);
         }
     }
 
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        self.0.read_to_end(buf)
+    /// Describes how this reader splits the input, used in timeout reports.
+    fn describe_split(&self) -> String {
+        match &self.reader {
+            SplitReader::Breaking(reader) if reader.greedy > 0 => format!("the first read returned {} bytes", reader.greedy),
+            SplitReader::Breaking(_) => "reading byte-by-byte".to_owned(),
+            SplitReader::Searching(reader) => format!("the input was split at position {}", reader.split),
+        }
     }
-}
 
-struct BreakingReader<'a>(&'a [u8]);
+    /// Checks whether reading after EOF is allowed, returns `true` if EOF was already reached.
+    fn check_read_after_eof(&self, wants_data: bool) -> io::Result<bool> {
+        if self.at_eof && wants_data && self.config.forbid_read_after_eof {
+            return Err(self.fail("consumer read after EOF"));
+        }
+        Ok(self.at_eof)
+    }
 
-impl io::Read for BreakingReader<'_> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() > 1 && self.0.len() > 1 {
-            buf[1] = !self.0[1];
+    /// Fails if the whole input was read already and trailing reads are forbidden.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn check_trailing_read(&mut self) -> io::Result<()> {
+        if self.config.forbid_trailing_read && !self.at_eof && self.position() >= self.input.len() {
+            // the trailing read is the culprit rather than the read crossing the split
+            if let SplitReader::Searching(reader) = &mut self.reader {
+                reader.backtrace.capture();
+            }
+            return Err(self.fail("consumer issued a trailing read after the whole input was read"));
         }
-        // intentional panic when buf.len() == 0: buggy use of the reader
-        self.0.read(&mut buf[..1])
+        Ok(())
     }
 
-    // read_exact is correct usage, so skip the BS
-    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
-        self.0.read_exact(buf)
+    /// Returns the injected error if the position at which it should be returned was reached.
+    fn check_injection(&self) -> io::Result<()> {
+        match self.injection {
+            Some(injection) if injection.injected.get().is_none() && self.position() >= injection.pos => {
+                injection.injected.set(Some(self.position()));
+                Err(io::Error::new(injection.kind, "error injected by io_check"))
+            },
+            _ => Ok(()),
+        }
     }
 
-    // read_to_end is correct usage, so skip the BS
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        self.0.read_to_end(buf)
+    /// Fails the test, returning the failure as an error if the closure returns `io::Result`.
+    ///
+    /// The error is recognized when the closure returns it so consumers using `?` report the
+    /// failure properly. Otherwise this panics.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn fail(&self, message: &'static str) -> io::Error {
+        if self.config.smuggle_errors {
+            error::smuggle(message)
+        } else {
+            panic!("{}", message)
+        }
+    }
+
+    /// Position of the next byte returned by the reader.
+    fn position(&self) -> usize {
+        match &self.reader {
+            SplitReader::Breaking(reader) => reader.pos,
+            SplitReader::Searching(reader) => reader.pos,
+        }
+    }
+
+    /// Detects consumers looping on EOF
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn check_eof(&mut self, buf: &[u8], result: &io::Result<usize>) -> io::Result<()> {
+        match result {
+            Ok(0) if !buf.is_empty() => {
+                self.at_eof = true;
+                self.eof_reads += 1;
+                if self.eof_reads > self.config.max_eof_reads {
+                    return Err(self.fail("consumer did not stop on EOF (read returned 0 repeatedly)"));
+                }
+            },
+            Ok(0) => (),
+            _ => self.eof_reads = 0,
+        }
+        Ok(())
     }
 }
 
-struct SearchingReader<'a> {
-    left: &'a [u8],
-    right: &'a [u8],
-    backtrace: BacktraceStorageMut<'a>,
+impl fmt::Debug for TestReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let consumed = self.position();
+        let (mode, upcoming_end) = match &self.reader {
+            SplitReader::Breaking(_) => ("breaking", self.input.len()),
+            SplitReader::Searching(reader) if consumed < reader.split => ("searching", reader.split),
+            SplitReader::Searching(_) => ("searching", self.input.len()),
+        };
+        // one more byte than displayed so that `Truncated` knows there's more
+        let upcoming = self.input.bytes(consumed, upcoming_end.min(consumed + TRUNCATED_LEN + 1));
+        let mut debug = f.debug_struct("TestReader");
+        debug.field("mode", &mode);
+        if let SplitReader::Searching(reader) = &self.reader {
+            debug.field("split", &reader.split);
+        }
+        debug
+            .field("consumed", &consumed)
+            .field("remaining", &(self.input.len() - consumed))
+            .field("upcoming", &Truncated(&upcoming))
+            .finish()
+    }
 }
 
-impl<'a> SearchingReader<'a> {
-    fn new(input: &'a [u8], pos: usize, backtrace: BacktraceStorageMut<'a>) -> Self {
-        let (left, right) = input.split_at(pos);
-        SearchingReader {
-            left,
-            right,
-            backtrace,
+impl TestReader<'_> {
+    /// Reads the data split according to the mode, shared by `read` and `read_buf`.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn read_split(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            self.check_injection()?;
+            self.check_trailing_read()?;
+        }
+        let pos = self.position();
+        let result = if self.check_read_after_eof(!buf.is_empty())? {
+            Ok(0)
+        } else {
+            self.reader.read(buf)
+        };
+        self.check_eof(buf, &result)?;
+        match result {
+            Ok(returned) if returned > 0 && self.config.detect_shrinking_reads => self.check_shrinking(LastRead { requested: buf.len(), returned, })?,
+            _ => (),
         }
+        if let (Ok(&returned), Some(last_method)) = (result.as_ref(), self.last_method) {
+            last_method.set_read(LastRead { requested: buf.len(), returned, });
+        }
+        if let Ok(returned) = result {
+            self.config.trace(IoEvent::Read { pos, requested: buf.len(), returned, });
+        }
+        result
     }
 }
 
-impl io::Read for SearchingReader<'_> {
+impl Read for TestReader<'_> {
     #[cfg_attr(feature = "rust_1_46", track_caller)]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.left.is_empty() {
-            self.right.read(buf)
-        } else if self.left.len() < buf.len() {
-            // if there is a problem it's caused by function that called `read` at the moment it
-            // split - now. We don't know if there actually is a problem for this specific split,
-            // so we collect backtrace and decide later whether to keep it.
-            self.backtrace.capture();
-            buf[self.left.len()] = !self.right[0];
-            self.left.read(&mut buf[..self.left.len()])
+        self.track(Method::Read, Some(buf.len()));
+        if self.config.forbid_empty_reads && buf.is_empty() {
+            // with `track_caller` the panic points at the culprit
+            return Err(self.fail("consumer requested a zero-length read"));
+        }
+        self.read_split(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.track(Method::ReadExact, Some(buf.len()));
+        if self.config.forbid_empty_read_exact && buf.is_empty() {
+            return Err(self.fail("consumer requested a zero-length read_exact"));
+        }
+        if !buf.is_empty() {
+            self.check_injection()?;
+        }
+        if self.check_read_after_eof(!buf.is_empty())? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+        }
+        let pos = self.position();
+        self.reader.read_exact(buf)?;
+        self.config.trace(IoEvent::ReadExact { pos, len: buf.len(), });
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.track(Method::ReadToEnd, None);
+        self.check_injection()?;
+        let pos = self.position();
+        let returned = if self.check_read_after_eof(true)? {
+            0
+        } else {
+            self.reader.read_to_end(buf)?
+        };
+        self.config.trace(IoEvent::ReadToEnd { pos, returned, });
+        Ok(returned)
+    }
+
+    // Uninitialized bytes can not be observed without `unsafe`, so there's nothing to poison in
+    // the cursor. Reading into a temporary buffer splits the data the same way `read` does. The
+    // buffer only needs to hold the rest of the input and one more byte so that the read is still
+    // larger than the available data, not the whole capacity which may be huge.
+    #[cfg(feature = "read_buf")]
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn read_buf(&mut self, mut cursor: io::BorrowedCursor<'_>) -> io::Result<()> {
+        self.track(Method::ReadBuf, Some(cursor.capacity()));
+        let remaining = self.input.len().saturating_sub(self.position());
+        let mut buf = vec![0; cursor.capacity().min(remaining.saturating_add(1))];
+        let len = self.read_split(&mut buf)?;
+        cursor.append(&buf[..len]);
+        Ok(())
+    }
+}
+
+/// Seeking repositions the reader within the input.
+///
+/// The splitting and poisoning is applied to the data read after seeking the same way as before,
+/// so re-reading a region that was already read splits it again. This catches consumers that
+/// cached a value obtained from a mis-handled short read and seeked past it or re-read it.
+/// Seeking past the end is allowed, reads return EOF then.
+impl Seek for TestReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset_from = |base: u64, offset: i64| if offset < 0 {
+            base.checked_sub(offset.wrapping_neg() as u64)
         } else {
-            self.left.read(buf)
+            base.checked_add(offset as u64)
+        };
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => offset_from(self.input.len() as u64, offset),
+            SeekFrom::Current(offset) => offset_from(self.position() as u64, offset),
+        };
+        let pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))?;
+        let start = if pos > self.input.len() as u64 { self.input.len() } else { pos as usize };
+        match &mut self.reader {
+            SplitReader::Breaking(reader) => reader.pos = start,
+            SplitReader::Searching(reader) => reader.pos = start,
         }
+        // the consumer may legitimately read again after seeking back from EOF
+        self.at_eof = false;
+        self.eof_reads = 0;
+        self.config.trace(IoEvent::Seek { pos: start, });
+        Ok(pos)
     }
 }
 
-// we want proper doc at top-level of the crate
-pub(crate) mod hack {
-    use super::*;
+/// Buffer reporting how much of the data read into it the consumer used.
+///
+/// Data is read into the buffer using [`TestReader::read_tracked`], which doesn't poison the
+/// rest of the buffer, so the consumer may peek at the whole buffer, e.g. to decode it
+/// speculatively. Once the consumer knows how many bytes it actually used it calls
+/// [`consume`](Self::consume), which panics if it's more than the last read returned. Only the
+/// consumed bytes are checked this way, unlike with poisoning the bytes that were merely peeked
+/// at can not cause a failure.
+pub struct TrackedBuf<'b> {
+    buf: &'b mut [u8],
+    // number of bytes returned by the last read
+    filled: usize,
+    // number of bytes of the last read used by the consumer
+    consumed: usize,
+}
 
-    /// Tests whether the closure correctly handles split reads.
+impl<'b> TrackedBuf<'b> {
+    /// Creates the tracked buffer reading into `buf`.
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        TrackedBuf {
+            buf,
+            filled: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Returns the whole buffer.
     ///
-    /// This is the entry point of this crate for read testing.
-    /// You provide `input` that will be returned from reader and a closure that accepts the reader and
-    /// uses it to read the input. The closure should check if the decoded values equal to the expected
-    /// values and *panic* if not. (e.g. using `assert_eq!()`)
+    /// The bytes past [`filled`](Self::filled) were not returned by the last read, they are
+    /// left over from earlier reads or the initial content of the buffer.
+    pub fn buf(&self) -> &[u8] {
+        self.buf
+    }
+
+    /// Returns the data returned by the last read that was not consumed yet.
+    pub fn filled(&self) -> &[u8] {
+        &self.buf[self.consumed..self.filled]
+    }
+
+    /// Marks `amt` bytes of the data returned by the last read as used by the consumer.
     ///
-    /// For best results make sure no other inputs affect the test - the function should be pure.
-    /// It will be called once and if it panics it'll be called again multiple times with
-    /// differently-behaving readers.
-    pub fn test_read<F>(input: &[u8], f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
-        test_read_no_panic(input, f).unwrap_or_else(|error| error.panic())
+    /// Panics if the consumer used more bytes than the last read returned, since the rest of the
+    /// buffer doesn't contain the input.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn consume(&mut self, amt: usize) {
+        let available = self.filled - self.consumed;
+        assert!(amt <= available, "consumer used {} bytes of the buffer but the last read returned only {} unconsumed bytes", amt, available);
+        self.consumed += amt;
     }
 }
 
-fn test_read_no_panic<F>(input: &[u8], f: F) -> Result<(), Error> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
-    if input.len() < 2 {
-        panic!("Testing slices shorter than 2 bytes doesn't make sense");
-    }
-    catch_unwind(|| f(TestReader::breaking(input)))
-        .map_err(|unwind| {
-            // skip split at zero and end since those are non-sensical
-            let failure_info = (1..input.len()).find_map(|pos| {
-                let mut backtrace = None;
-                let backtrace_mut = BacktraceStorageMut::from_mut(&mut backtrace);
-                catch_unwind(|| f(TestReader::searching(input, pos, backtrace_mut)))
-                    .err()
-                    .map(|unwind| {
-                        crate::backtrace_impl::resolve(&mut backtrace);
-                        FailureInfo { unwind, pos, backtrace, }
-                    })
-            });
-            Error {
-                unwind,
-                failure_info,
-            }
-        })
+/// Data returned by the reader, possibly generated on the fly.
+#[derive(Clone, Copy)]
+enum Input<'a> {
+    Slice(&'a [u8]),
+    Generated {
+        len: usize,
+        generator: &'a (dyn Fn(usize) -> u8 + RefUnwindSafe + Sync),
+    },
 }
 
-type Unwind = Box<dyn std::any::Any + Send + 'static>;
+impl<'a> Input<'a> {
+    fn len(&self) -> usize {
+        match *self {
+            Input::Slice(slice) => slice.len(),
+            Input::Generated { len, .. } => len,
+        }
+    }
 
-struct FailureInfo {
-    unwind: Unwind,
-    pos: usize,
-    backtrace: Option<Backtrace>,
-}
+    fn get(&self, pos: usize) -> Option<u8> {
+        match *self {
+            Input::Slice(slice) => slice.get(pos).copied(),
+            Input::Generated { len, generator, } if pos < len => Some(generator(pos)),
+            Input::Generated { .. } => None,
+        }
+    }
 
-impl fmt::Debug for FailureInfo {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("FailureInfo")
-            .field("unwind", &format_args!("message: {:?}", get_panic_message(&self.unwind)))
-            .field("pos", &self.pos)
-            .finish()
+    /// Returns the bytes in the given range, generating them if needed.
+    fn bytes(&self, start: usize, end: usize) -> Cow<'a, [u8]> {
+        match *self {
+            Input::Slice(slice) => Cow::Borrowed(&slice[start..end]),
+            Input::Generated { generator, .. } => (start..end).map(generator).collect(),
+        }
+    }
+
+    /// Returns the first `len` bytes of the input.
+    fn prefix(&self, len: usize) -> Self {
+        match *self {
+            Input::Slice(slice) => Input::Slice(&slice[..len]),
+            Input::Generated { generator, .. } => Input::Generated { len, generator, },
+        }
+    }
+
+    /// Fills `buf` with the input starting at `pos` as much as possible, advancing `pos`.
+    fn read_at(&self, pos: &mut usize, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len() - *pos);
+        match *self {
+            Input::Slice(slice) => buf[..len].copy_from_slice(&slice[*pos..(*pos + len)]),
+            Input::Generated { generator, .. } => {
+                for (byte, i) in buf[..len].iter_mut().zip(*pos..) {
+                    *byte = generator(i);
+                }
+            },
+        }
+        *pos += len;
+        len
     }
 }
 
+/// Number of consecutive shrinking reads reported by `ReadTest::detect_shrinking_reads`.
+const MAX_SHRINKING_READS: usize = 3;
+
+/// Observes the position of a split and the requested buffer length.
+type SplitFn = dyn Fn(usize, usize) + Send + Sync + RefUnwindSafe;
 
-/// Test failure information
-struct Error {
-    unwind: Unwind,
-    failure_info: Option<FailureInfo>,
+/// Computes the poisoned byte from the input byte and its offset.
+type PoisonFn = dyn Fn(u8, usize) -> u8 + Send + Sync + RefUnwindSafe;
+
+/// Poisons the byte after the first `len` bytes of `buf`, `offset` is the position of the
+/// corresponding byte in the input.
+fn poison(buf: &mut [u8], len: usize, input: Input<'_>, offset: usize, config: &Config) {
+    if config.no_poison {
+        return;
+    }
+    let next = input.get(offset);
+    match config.poison.as_deref() {
+        Some(poison) => split::poison_with(buf, len, next.as_ref(), |next| poison(next, offset)),
+        None => split::poison(buf, len, next.as_ref()),
+    }
 }
 
-impl Error {
-    /// Resumes panic with relevant error information added if possible
-    fn panic(self) -> ! {
-        let first_panic_message = get_panic_message(&self.unwind);
-        match self.failure_info {
-            Some(FailureInfo { unwind, pos, backtrace }) => {
-                let backtrace = DisplayBacktrace::read(&backtrace);
-                let second_panic_message = get_panic_message(&unwind);
-                match (first_panic_message, second_panic_message) {
-                    (Some(msg1), Some(msg2)) if msg1 == msg2 => panic!("test failed at position {}: {}\n{}", pos, msg1, backtrace),
-                    (Some(msg1), Some(msg2)) => panic!("test failed with message \"{}\" but a different message was encountered when breaking at position {}: {}\n{}", msg1, pos, msg2, backtrace),
-                    (Some(msg), None) => panic!("test failed with message \"{}\" but a different panic with unknown message was encountered at position {}\n{}", msg, pos, backtrace),
-                    (None, Some(msg)) => panic!("test failed with unknown message but a different panic was encountered at position {}: {}\n{}", pos, msg, backtrace),
-                    (None, None) => panic!("test failed at position {} with unknown messages\n{}", pos, backtrace),
-                }
+struct BreakingReader<'a> {
+    input: Input<'a>,
+    // position of the next returned byte
+    pos: usize,
+    // number of bytes that are still returned at once before reading byte-by-byte
+    greedy: usize,
+    // sizes of chunks returned by the following `read` calls before reading byte-by-byte
+    schedule: &'a [usize],
+    // sorted positions that reads never cross
+    splits: &'a [usize],
+    config: &'a Config,
+    // the read goes to a `TrackedBuf` which checks the consumption instead of poisoning
+    tracked: bool,
+    // counts the reads split at an interior position
+    split_count: Option<&'a Cell<usize>>,
+}
+
+impl io::Read for BreakingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // reading into an empty buffer is allowed by the `Read` contract
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = match self.schedule.split_first() {
+            Some((&chunk, rest)) => {
+                self.schedule = rest;
+                chunk.min(buf.len()).max(1)
             },
-            None => {
-                match first_panic_message {
-                    Some(msg) => panic!("test failed at unknown position: {}", msg),
-                    None => resume_unwind(self.unwind),
+            None => split::breaking_len(self.greedy, buf.len()),
+        };
+        let len = match self.splits.iter().find(|split| **split > self.pos) {
+            Some(split) => len.min(split - self.pos),
+            None => len,
+        };
+        if len < buf.len() && self.pos + len < self.input.len() {
+            self.config.notify_split(self.pos + len, buf.len());
+            if let Some(count) = self.split_count {
+                count.set(count.get() + 1);
+            }
+        }
+        if !self.tracked {
+            poison(buf, len, self.input, self.pos + len, self.config);
+        }
+        let read = self.input.read_at(&mut self.pos, &mut buf[..len]);
+        self.greedy = self.greedy.saturating_sub(read);
+        Ok(read)
+    }
+
+    // read_exact is correct usage, so skip the BS unless requested
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        if self.config.sabotage_all {
+            while !buf.is_empty() {
+                match self.read(buf)? {
+                    0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                    read => buf = &mut buf[read..],
                 }
+            }
+            return Ok(());
+        }
+        // same as reading from a slice, the input is consumed even if it's too short
+        if self.input.read_at(&mut self.pos, buf) < buf.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+        }
+        self.greedy = self.greedy.saturating_sub(buf.len());
+        Ok(())
+    }
+
+    // read_to_end is correct usage, so skip the BS unless requested
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.config.sabotage_all {
+            return read_to_end_split(self, buf);
+        }
+        self.greedy = 0;
+        Ok(read_rest(self.input, &mut self.pos, buf))
+    }
+}
+
+/// Reads until EOF using `read` so that the data is split, used by `ReadTest::sabotage_all`.
+fn read_to_end_split<R: Read>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0u8; 64];
+    let mut total = 0;
+    loop {
+        match reader.read(&mut chunk)? {
+            0 => return Ok(total),
+            read => {
+                buf.extend_from_slice(&chunk[..read]);
+                total += read;
             },
         }
     }
 }
 
-impl fmt::Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Error")
-            .field("unwind", &format_args!("message: {:?}", get_panic_message(&self.unwind)))
-            .field("failure_info", &self.failure_info)
-            .finish()
+/// Appends the rest of the input starting at `pos` to `buf` at once, advancing `pos`.
+fn read_rest(input: Input<'_>, pos: &mut usize, buf: &mut Vec<u8>) -> usize {
+    let start = buf.len();
+    buf.resize(start + (input.len() - *pos), 0);
+    input.read_at(pos, &mut buf[start..])
+}
+
+/// The reader implementing the splitting, chosen by the mode of the test.
+enum SplitReader<'a> {
+    Breaking(BreakingReader<'a>),
+    Searching(SearchingReader<'a>),
+}
+
+impl SplitReader<'_> {
+    fn set_tracked(&mut self, tracked: bool) {
+        match self {
+            SplitReader::Breaking(reader) => reader.tracked = tracked,
+            SplitReader::Searching(reader) => reader.tracked = tracked,
+        }
     }
 }
 
+impl io::Read for SplitReader<'_> {
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SplitReader::Breaking(reader) => reader.read(buf),
+            SplitReader::Searching(reader) => reader.read(buf),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            SplitReader::Breaking(reader) => reader.read_exact(buf),
+            SplitReader::Searching(reader) => reader.read_exact(buf),
+        }
+    }
 
-fn get_panic_message(unwind: &Unwind) -> Option<&str> {
-    match unwind.as_ref().downcast_ref::<&'static str>() {
-        Some(msg) => Some(*msg),
-        None => match unwind.as_ref().downcast_ref::<String>() {
-            Some(msg) => Some(msg.as_str()),
-            // Copy what rustc does in the default panic handler
-            None => None,
-        },
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        match self {
+            SplitReader::Breaking(reader) => reader.read_to_end(buf),
+            SplitReader::Searching(reader) => reader.read_to_end(buf),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::io::Read;
-    use super::test_read_no_panic;
+struct SearchingReader<'a> {
+    input: Input<'a>,
+    // position of the next returned byte
+    pos: usize,
+    // position of the split in the whole input, doesn't change when seeking
+    split: usize,
+    backtrace: BacktraceStorageMut<'a>,
+    config: &'a Config,
+    // the read goes to a `TrackedBuf` which checks the consumption instead of poisoning
+    tracked: bool,
+}
+
+impl io::Read for SearchingReader<'_> {
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = match split::split_len(self.pos, self.split, buf.len()) {
+            Some(len) => {
+                // if there is a problem it's caused by function that called `read` at the moment it
+                // split - now. We don't know if there actually is a problem for this specific split,
+                // so we collect backtrace and decide later whether to keep it.
+                self.backtrace.capture();
+                self.config.notify_split(self.split, buf.len());
+                if !self.tracked {
+                    poison(buf, len, self.input, self.split, self.config);
+                }
+                &mut buf[..len]
+            },
+            None => buf,
+        };
+        Ok(self.input.read_at(&mut self.pos, buf))
+    }
 
-    impl super::Error {
-        fn panic_message1(&self) -> Option<&str> {
-            super::get_panic_message(&self.unwind)
+    // read_to_end is correct usage, so it's not split unless requested, just like in
+    // `BreakingReader`. Splitting it would also capture the backtrace of `read_to_end` instead
+    // of the consumer.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.config.sabotage_all {
+            return read_to_end_split(self, buf);
         }
+        Ok(read_rest(self.input, &mut self.pos, buf))
+    }
+}
+
+/// The last `Read` method called by the closure, the different one called before it and the
+/// last `read` call, reported on failure, along with the number of reads the breaking reader
+/// split, reported on success.
+// It's UnwindSafe because setting the `Cell`s can not leave them in inconsistent state
+struct LastMethod {
+    method: AssertUnwindSafe<Cell<Option<MethodCall>>>,
+    previous: AssertUnwindSafe<Cell<Option<MethodCall>>>,
+    read: AssertUnwindSafe<Cell<Option<LastRead>>>,
+    splits: AssertUnwindSafe<Cell<usize>>,
+}
 
-        fn panic_message2(&self) -> Option<&str> {
-            self.failure_info.as_ref().and_then(|info| super::get_panic_message(&info.unwind))
+impl LastMethod {
+    fn new() -> Self {
+        LastMethod {
+            method: AssertUnwindSafe(Cell::new(None)),
+            previous: AssertUnwindSafe(Cell::new(None)),
+            read: AssertUnwindSafe(Cell::new(None)),
+            splits: AssertUnwindSafe(Cell::new(0)),
         }
+    }
+
+    /// Returns the number of reads split by the breaking reader since the last call.
+    fn take_splits(&self) -> usize {
+        self.splits.replace(0)
+    }
 
-        fn pos(&self) -> Option<usize> {
-            self.failure_info.as_ref().map(|info| info.pos)
+    fn set(&self, name: Method, len: Option<usize>) {
+        let call = MethodCall { name, len, };
+        if let Some(current) = self.method.replace(Some(call)) {
+            if current.name != name {
+                self.previous.set(Some(current));
+            }
         }
     }
 
-    #[test]
-    fn basic() {
-        let err = test_read_no_panic(&[1, 0], |mut reader| {
-            let mut buf = [0u8; 2];
-            reader.read(&mut buf).unwrap();
-            let num = u16::from_le_bytes(buf);
-            assert_eq!(num, 1);
-        }).unwrap_err();
+    /// Returns the last change of the called method, must be called before `take` which resets it.
+    fn take_transition(&self) -> Option<Box<MethodTransition>> {
+        let from = self.previous.take()?;
+        let to = self.method.get()?;
+        Some(Box::new(MethodTransition { from, to, }))
+    }
 
-        assert_eq!(err.panic_message1(), Some("assertion failed: `(left == right)`\n  left: `65281`,\n right: `1`"));
-        assert_eq!(err.panic_message2(), err.panic_message1());
-        assert_eq!(err.pos().unwrap(), 1);
+    fn set_read(&self, read: LastRead) {
+        self.read.set(Some(read));
     }
 
-    #[test]
-    fn read_exact_followed_by_read() {
-        let err = test_read_no_panic(&[1, 0, 1, 0], |mut reader| {
-            let mut buf = [0u8; 2];
-            reader.read_exact(&mut buf).unwrap();
-            let num = u16::from_le_bytes(buf);
-            assert_eq!(num, 1);
+    /// Returns the last `read` call, must be called before `take` which resets it.
+    fn take_read(&self) -> Option<LastRead> {
+        self.read.take()
+    }
 
-            let mut buf = [0u8; 2];
-            reader.read(&mut buf).unwrap();
-            let num = u16::from_le_bytes(buf);
-            assert_eq!(num, 1);
-        }).unwrap_err();
+    fn take(&self) -> Option<Method> {
+        self.read.take();
+        self.previous.take();
+        self.method.take().map(|call| call.name)
+    }
+}
 
-        assert_eq!(err.panic_message1(), Some("assertion failed: `(left == right)`\n  left: `65281`,\n right: `1`"));
-        assert_eq!(err.panic_message2(), err.panic_message1());
-        assert_eq!(err.pos().unwrap(), 3);
+/// Error returned by the reader instead of data at the given position.
+// It's UnwindSafe because setting the `Cell` can not leave it in inconsistent state
+struct Injection {
+    pos: usize,
+    kind: io::ErrorKind,
+    // position at which the error was actually returned, it's the first read at or after `pos`
+    injected: AssertUnwindSafe<Cell<Option<usize>>>,
+}
+
+/// Settings of the test shared by all readers
+#[derive(Clone)]
+struct Config {
+    max_eof_reads: usize,
+    forbid_read_after_eof: bool,
+    forbid_trailing_read: bool,
+    forbid_empty_reads: bool,
+    forbid_empty_read_exact: bool,
+    detect_shrinking_reads: bool,
+    // return failures detected by the reader as errors instead of panicking
+    smuggle_errors: bool,
+    sabotage_all: bool,
+    // computes the poisoned byte instead of negating it
+    poison: Option<Arc<PoisonFn>>,
+    // leave the buffer after the returned data untouched
+    no_poison: bool,
+    // called whenever a read is split
+    on_split: Option<Arc<SplitFn>>,
+    // called for each IO call of the closure
+    trace: Option<Arc<TraceFn>>,
+    normalize_message: Option<NormalizeFn>,
+    allow_unused_reader: bool,
+    sweep_first_read: bool,
+    shrink_input: bool,
+    // split position the search starts at
+    search_from: Option<usize>,
+    // search the split positions from the end of the input
+    search_reverse: bool,
+    // split positions tried before all others
+    segment_boundaries: Vec<usize>,
+    // number of times each position is tried before it's considered passing
+    position_attempts: usize,
+    // search the split positions right away instead of running the breaking pass first
+    skip_breaking: bool,
+    // keep trying the positions after the reported failure
+    all_failures: bool,
+}
+
+/// Configuration used by default and by standalone readers.
+static DEFAULT_CONFIG: Config = Config {
+    max_eof_reads: 1024,
+    forbid_read_after_eof: false,
+    forbid_trailing_read: false,
+    forbid_empty_reads: false,
+    forbid_empty_read_exact: false,
+    detect_shrinking_reads: false,
+    smuggle_errors: false,
+    sabotage_all: false,
+    poison: None,
+    no_poison: false,
+    on_split: None,
+    trace: None,
+    normalize_message: None,
+    allow_unused_reader: false,
+    sweep_first_read: false,
+    shrink_input: false,
+    search_from: None,
+    search_reverse: false,
+    segment_boundaries: Vec::new(),
+    position_attempts: 1,
+    skip_breaking: false,
+    all_failures: false,
+};
+
+impl Config {
+    fn notify_split(&self, pos: usize, requested: usize) {
+        if let Some(on_split) = &self.on_split {
+            on_split(pos, requested);
+        }
     }
 
-    #[test]
-    fn no_error() {
-        test_read_no_panic(&[1, 0], |mut reader| {
-            let mut buf = [0u8; 2];
-            reader.read_exact(&mut buf).unwrap();
-            let num = u16::from_le_bytes(buf);
-            assert_eq!(num, 1);
-        }).unwrap();
+    fn trace(&self, event: IoEvent) {
+        if let Some(trace) = &self.trace {
+            trace(event);
+        }
+    }
+}
+
+/// Configurable test of `Read` consumers.
+///
+/// This allows tweaking the behavior of the test. [`test_read`] is equivalent to
+/// `ReadTest::new(input).run(f)`.
+///
+/// [`test_read`]: super::test_read
+pub struct ReadTest<'a> {
+    input: Input<'a>,
+    config: Config,
+    reporter: &'a dyn Reporter,
+}
+
+impl<'a> ReadTest<'a> {
+    /// Creates the test providing `input` to the closure.
+    pub fn new(input: &'a [u8]) -> Self {
+        Self::with_input(Input::Slice(input))
+    }
+
+    /// Creates the test providing `len` bytes returned by `generator` to the closure.
+    ///
+    /// `generator` is called with the offset of each byte and must return the byte at that
+    /// offset. This avoids storing huge inputs in memory at the cost of calling `generator` for
+    /// each read byte, possibly multiple times. The closure is called repeatedly to search for
+    /// the failing split so `generator` must be pure - returning different bytes in different
+    /// runs makes the search report nonsense.
+    pub fn generated<G>(len: usize, generator: &'a G) -> Self where G: Fn(usize) -> u8 + RefUnwindSafe + Sync {
+        Self::with_input(Input::Generated { len, generator, })
+    }
+
+    fn with_input(input: Input<'a>) -> Self {
+        ReadTest {
+            input,
+            config: DEFAULT_CONFIG.clone(),
+            reporter: &PanicReporter,
+        }
+    }
+
+    /// Sets the number of consecutive reads returning 0 after which the test fails.
+    ///
+    /// A consumer that doesn't stop on EOF would loop forever on a real stream, so the reader
+    /// panics instead of hanging the test. The default is 1024.
+    pub fn max_eof_reads(mut self, max: usize) -> Self {
+        self.config.max_eof_reads = max;
+        self
+    }
 
+    /// Makes any read after the reader returned EOF fail the test.
+    ///
+    /// Once a read returns `Ok(0)` the reader stays at EOF, so a consumer treating `Ok(0)` as
+    /// "try again later" never gets more data. This option reports such consumer directly with
+    /// "consumer read after EOF" message instead of waiting for it to fail on missing data.
+    pub fn forbid_read_after_eof(mut self) -> Self {
+        self.config.forbid_read_after_eof = true;
+        self
+    }
+
+    /// Makes a `read` call after the whole input was read fail the test.
+    ///
+    /// A consumer decoding data of known length should stop reading once it has all the bytes.
+    /// An extra `read` returning `Ok(0)` is not wrong for a `Read` but on a real stream it blocks
+    /// until the peer sends more data or closes the connection, e.g. deadlocking a
+    /// request-response protocol. The culprit of the failure is the trailing `read` call. Don't
+    /// use this for consumers reading until EOF.
+    pub fn forbid_trailing_read(mut self) -> Self {
+        self.config.forbid_trailing_read = true;
+        self
+    }
+
+    /// Makes any `read` call with an empty buffer fail the test.
+    ///
+    /// Reading into an empty buffer is allowed by the `Read` contract and returns `Ok(0)` by
+    /// default but it's usually a logic error in the consumer, e.g. a miscomputed remaining
+    /// length. This option reports such calls with "consumer requested a zero-length read"
+    /// message pointing at the culprit if `rust_1_46` feature is enabled.
+    pub fn forbid_empty_reads(mut self) -> Self {
+        self.config.forbid_empty_reads = true;
+        self
+    }
+
+    /// Makes any `read_exact` call with an empty buffer fail the test.
+    ///
+    /// `read_exact` with an empty buffer returns `Ok(())` without touching the input, even at EOF,
+    /// so code using it to check whether any bytes remain is broken. This option reports such
+    /// calls with "consumer requested a zero-length read_exact" message. The culprit is not
+    /// pointed at since `read_exact` can not be `#[track_caller]`.
+    pub fn forbid_empty_read_exact(mut self) -> Self {
+        self.config.forbid_empty_read_exact = true;
+        self
+    }
+
+    /// Makes the test fail if the closure requests progressively shrinking buffers.
+    ///
+    /// A correct consumer reading in a loop requests the remaining length, which shrinks exactly
+    /// by the amount of returned data. If the remaining length is computed wrong each `read`
+    /// requests less and less and the consumer never gets all the data. With this option the
+    /// reader reports several consecutive `read` calls requesting less than the previous request
+    /// minus the returned data with a hint pointing at the length computation. This is a
+    /// heuristic which may flag consumers reading fields of decreasing sizes, so it's off by
+    /// default.
+    pub fn detect_shrinking_reads(mut self) -> Self {
+        self.config.detect_shrinking_reads = true;
+        self
+    }
+
+    /// Allows the closure to not read from the reader at all.
+    ///
+    /// A closure ignoring the reader (e.g. decoding a captured slice by mistake) passes trivially,
+    /// so by default the test panics if the closure didn't call any `Read` method in the
+    /// byte-by-byte pass. This option disables the check for intentionally empty closures.
+    pub fn allow_unused_reader(mut self) -> Self {
+        self.config.allow_unused_reader = true;
+        self
+    }
+
+    /// Splits the input in `read_exact` and `read_to_end` too.
+    ///
+    /// By default these methods return the data at once in the byte-by-byte pass since they are
+    /// correct usage of the reader, `read_to_end` does so in the search as well. With this option
+    /// they are implemented on top of the splitting `read`, just like the default implementations
+    /// in `std` are. This is useful to confirm that
+    /// code mixing these methods with `read` doesn't rely on their particular implementation.
+    pub fn sabotage_all(mut self) -> Self {
+        self.config.sabotage_all = true;
+        self
+    }
+
+    /// Computes the poisoned byte using the closure instead of negating the input byte.
+    ///
+    /// The byte of the buffer right after the returned data is set to a value differing from the
+    /// input so that consumers assuming the whole buffer was filled see invalid data. By default
+    /// it's the bitwise negation of the corresponding input byte which may happen to be valid in
+    /// some formats, e.g. a plausible length prefix. The closure is called with the input byte
+    /// and its offset and should return a byte that is guaranteed to be invalid at that offset.
+    pub fn poison_with<P>(mut self, poison: P) -> Self where P: Fn(u8, usize) -> u8 + Send + Sync + RefUnwindSafe + 'static {
+        self.config.poison = Some(Arc::new(poison));
+        self
+    }
+
+    /// Only shortens the reads, leaving the rest of the buffer untouched.
+    ///
+    /// By default the byte of the buffer right after the returned data is poisoned, so a failure
+    /// may be caused either by the consumer mishandling the short read itself or by it using the
+    /// poisoned byte. If the test still fails with this option the consumer mishandles the short
+    /// read regardless of the buffer contents, e.g. it uses stale data from a previous read. If
+    /// it passes, the failure was caused by reading the poisoned byte. This is meant for
+    /// diagnosing failures, poisoning finds more bugs. Overrides
+    /// [`poison_with`](Self::poison_with).
+    pub fn no_poison(mut self) -> Self {
+        self.config.no_poison = true;
+        self
+    }
+
+    /// Calls the closure whenever the reader splits a read.
+    ///
+    /// The closure is called with the position in the input at which the returned data ends and
+    /// the length of the buffer passed to the split call, before the call returns. A read is
+    /// split when it returns less data than requested even though more input is available. This
+    /// is useful for diagnosing why a particular split does or doesn't trigger a bug. It's called
+    /// in all runs, including the search, but not for reads that are never split.
+    pub fn on_split<S>(mut self, on_split: S) -> Self where S: Fn(usize, usize) + Send + Sync + RefUnwindSafe + 'static {
+        self.config.on_split = Some(Arc::new(on_split));
+        self
+    }
+
+    /// Calls the closure for each IO call the tested closure makes.
+    ///
+    /// The trace contains the method, the position in the input and the lengths involved, see
+    /// [`IoEvent`] for details. Unlike [`on_split`](Self::on_split) this observes all calls, so
+    /// it's possible to reconstruct exactly what the tested code did with each reader, including
+    /// the ones used by the search. Nothing is traced if no tracer is installed.
+    pub fn trace<T>(mut self, tracer: T) -> Self where T: Fn(IoEvent) + Send + Sync + RefUnwindSafe + 'static {
+        self.config.trace = Some(Arc::new(tracer));
+        self
+    }
+
+    /// Normalizes panic messages using the function before comparing them.
+    ///
+    /// When the failure is reported the message of the breaking pass is compared with the one
+    /// found by the search and if they differ both are reported as two possibly unrelated
+    /// failures. Messages containing volatile content, e.g. addresses, timestamps or random
+    /// identifiers, differ between runs even if the failure is the same, so the function should
+    /// replace such content with a placeholder. It's also used when counting distinct messages
+    /// at later positions. The messages themselves are reported unchanged.
+    pub fn normalize_messages(mut self, normalize: fn(&str) -> String) -> Self {
+        self.config.normalize_message = Some(normalize);
+        self
+    }
+
+    /// Additionally tests the closure with readers returning a larger chunk first.
+    ///
+    /// Some bugs only appear when the first read returns more than one byte but a later read is
+    /// short - e.g. a decoder setting up its state from a big initial read and mishandling the
+    /// rest of the stream. If this is enabled and the usual test passes the closure is called
+    /// again with readers returning the first `k` bytes at once and then one byte per call, for
+    /// each `k` from 2 to `input.len() - 1`. The failure message reports `k`.
+    pub fn sweep_first_read(mut self) -> Self {
+        self.config.sweep_first_read = true;
+        self
+    }
+
+    /// Looks for the shortest prefix of the input reproducing a failure.
+    ///
+    /// If the test fails at a known split position, the closure is tested again with prefixes of
+    /// the input, starting with the shortest one. A prefix reproduces the failure if the closure
+    /// passes when reading it whole but fails at a split position. The shortest such prefix is
+    /// reported so that it can be used in a minimal regression test, the position then refers to
+    /// it.
+    ///
+    /// This only helps if the closure checks the decoded value against the input (e.g. by
+    /// round-tripping) rather than a fixed value and it calls the closure many more times, so
+    /// it's off by default.
+    pub fn shrink_input(mut self) -> Self {
+        self.config.shrink_input = true;
+        self
+    }
+
+    /// Starts searching for the failing split position at `pos`.
+    ///
+    /// By default the positions are tried from 1 up, so for large inputs with a bug near a known
+    /// offset most of the time is spent before reaching it. With this the positions are tried in
+    /// the order of their distance from `pos` - `pos` itself, then `pos - 1`, `pos + 1` and so on.
+    /// If nothing fails near `pos` the search eventually tries all positions, so a failure is
+    /// never missed.
+    ///
+    /// Note however that the reported position is the one closest to `pos`, which may not be the
+    /// first one. If the closure also fails at an earlier position because of a different bug that
+    /// bug is not reported (it's not listed in `ReadFailure::all_failures` either), so use this
+    /// only while investigating a specific failure and keep the default for regular tests. It
+    /// doesn't affect `run_parallel`.
+    ///
+    /// Panics if `pos` is not a valid split position, that is zero or not less than the length of
+    /// the input.
+    pub fn search_from(mut self, pos: usize) -> Self {
+        assert!(pos > 0 && pos < self.input.len(), "the search must start at a split position between 1 and {}, {} given", self.input.len().saturating_sub(1), pos);
+        self.config.search_from = Some(pos);
+        self
+    }
+
+    /// Searches the split positions from the end of the input toward the start.
+    ///
+    /// Bugs near EOF, e.g. in handling of a trailer or a checksum, are only reached after all the
+    /// earlier positions passed, which takes long for large inputs. With this the last position is
+    /// tried first so such bugs are found faster and the latest failing position is reported.
+    ///
+    /// Just like with [`search_from`](Self::search_from), failures at earlier positions are not
+    /// reported, so keep the default for regular tests. If `search_from` is set as well it takes
+    /// precedence. It doesn't affect `run_parallel`.
+    pub fn search_reverse(mut self) -> Self {
+        self.config.search_reverse = true;
+        self
+    }
+
+    /// Keeps trying the split positions after finding the failing one to collect all failures.
+    ///
+    /// By default the search stops at the first failing position, only continuing to localize a
+    /// second bug if the breaking pass failed with a different message. With this the remaining
+    /// positions are tried as well, so [`ReadFailure::all_failures`] returns the whole failure
+    /// surface and the message notes the positions reproducing the same failure and the number of
+    /// distinct messages. This costs a closure run per position so it's not the default. It
+    /// doesn't affect `run_parallel`.
+    pub fn find_all_failures(mut self) -> Self {
+        self.config.all_failures = true;
+        self
+    }
+
+    /// Tries the boundaries of logically concatenated segments before other split positions.
+    ///
+    /// Code stitching multiple buffers together, e.g. a header and a body coming from different
+    /// sources, tends to have off-by-one bugs exactly at the joins. The `boundaries` are the
+    /// positions at which the segments join and if the closure fails at one of them the failure
+    /// message says which one. [`test_read_segments`] computes them from the segments.
+    ///
+    /// If no boundary fails the remaining positions are searched as usual. Just like with
+    /// [`search_from`](Self::search_from) the reported position may not be the first failing one.
+    ///
+    /// Panics if the boundaries are not increasing valid split positions.
+    ///
+    /// [`test_read_segments`]: super::test_read_segments
+    pub fn segment_boundaries(mut self, boundaries: &[usize]) -> Self {
+        let len = self.input.len();
+        assert!(boundaries.iter().all(|pos| *pos > 0 && *pos < len), "segment boundaries {:?} must be split positions between 1 and {}", boundaries, len.saturating_sub(1));
+        assert!(boundaries.windows(2).all(|pair| pair[0] < pair[1]), "segment boundaries {:?} must be strictly increasing", boundaries);
+        self.config.segment_boundaries = boundaries.to_vec();
+        self
+    }
+
+    /// Tests only readers splitting the input at a single position, skipping the breaking pass.
+    ///
+    /// The breaking pass returns a single byte per call and poisons the byte after it in the
+    /// buffer. A consumer legitimately reading ahead into the buffer, e.g. one that speculatively
+    /// decodes the whole buffer and discards the invalid part later, may trip over the poison for
+    /// reasons unrelated to handling short reads. With this the closure is only called with the
+    /// searching readers, which return whole reads except for the one crossing the split
+    /// position, so short reads are still checked and the byte after the data is poisoned only
+    /// for that read.
+    ///
+    /// The trade-off is that the closure is called for every split position even if it's correct,
+    /// making passing tests about as slow as failing ones, and bugs showing up only if multiple
+    /// reads are short are not detected since there's a single short read in each run. The
+    /// default keeps the breaking pass. Consumers reading through [`TrackedBuf`] avoid the poison
+    /// without losing it.
+    pub fn skip_breaking(mut self) -> Self {
+        self.config.skip_breaking = true;
+        self
+    }
+
+    /// Tries each split position up to `attempts` times before deciding the closure passed.
+    ///
+    /// The closure should be pure - returning the same result for the same split. If it isn't,
+    /// e.g. because it depends on the iteration order of a `HashMap`, the failure may not
+    /// reproduce at the position causing it and the search reports a later one or none at all.
+    /// Retrying works around it at the cost of calling the closure `attempts` times for each
+    /// passing position, so making the closure deterministic is preferable.
+    ///
+    /// Panics if `attempts` is zero.
+    pub fn retry_positions(mut self, attempts: usize) -> Self {
+        assert!(attempts > 0, "each position must be tried at least once");
+        self.config.position_attempts = attempts;
+        self
+    }
+
+    /// Presents failures using `reporter` instead of panicking.
+    ///
+    /// This affects all `run*` methods except [`try_run`](Self::try_run) which returns the
+    /// failure. Timeouts of [`run_with_timeout`](Self::run_with_timeout) still panic.
+    pub fn report_with(mut self, reporter: &'a dyn Reporter) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Runs the test, panicking if it fails.
+    ///
+    /// See [`test_read`] for more information.
+    ///
+    /// [`test_read`]: super::test_read
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run<F>(self, f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        if let Err(error) = self.run_no_panic(f) {
+            self.reporter.on_read_failure(ReadFailure(error));
+        }
+    }
+
+    /// Runs the test with a closure returning `io::Result`, panicking if it fails.
+    ///
+    /// This allows using `?` inside the closure. Failures detected by the reader itself (e.g. by
+    /// [`forbid_read_after_eof`](Self::forbid_read_after_eof)) are returned from the reader as
+    /// errors and reported properly when the closure returns them. Other errors returned by the
+    /// closure are reported as unexpected IO errors.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run_fallible<T, F>(mut self, f: F) where F: Fn(TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        self.config.smuggle_errors = true;
+        self.run(|reader| {
+            if let Err(error) = f(reader) {
+                match error::unsmuggle(&error) {
+                    Some(failure) => panic!("{}", failure),
+                    None => panic!("unexpected IO error during test: {}", error),
+                }
+            }
+        })
+    }
+
+    /// Runs the test, failing if it doesn't finish within `timeout`.
+    ///
+    /// Some bugs cause infinite loops instead of panics, e.g. retrying a short read forever. To
+    /// turn such hang into a failure the test is run on a helper thread and if it doesn't finish
+    /// in time the test panics reporting the split that was being tested. The timeout applies to
+    /// the whole test, not individual calls of the closure.
+    ///
+    /// Threads can not be killed so the helper thread keeps running in the background after a
+    /// timeout. This is why the closure has to be `Send + Sync + 'static`.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run_with_timeout<F>(self, timeout: Duration, f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe + Send + Sync + 'static {
+        let location = backtrace_impl::test_location();
+        // the generator is borrowed so the input has to be materialized to move it to the thread
+        let input = self.input.bytes(0, self.input.len()).into_owned();
+        let config = self.config;
+        let reporter = self.reporter;
+        let current_split = Arc::new(Mutex::new(String::new()));
+        let (sender, receiver) = mpsc::channel();
+        let thread = {
+            let current_split = Arc::clone(&current_split);
+            std::thread::spawn(move || {
+                let test = ReadTest { input: Input::Slice(&input), config, reporter: &PanicReporter, };
+                let result = test.run_no_panic(|reader| {
+                    *current_split.lock().unwrap_or_else(|error| error.into_inner()) = reader.describe_split();
+                    f(reader)
+                });
+                // the receiver is gone if the test timed out already
+                let _ = sender.send(result);
+            })
+        };
+        match receiver.recv_timeout(timeout) {
+            Ok(Ok(_)) => (),
+            Ok(Err(mut error)) => {
+                error.location = location;
+                reporter.on_read_failure(ReadFailure(error));
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let current_split = current_split.lock().unwrap_or_else(|error| error.into_inner());
+                panic!("{} failed: closure did not terminate within {:?} when {}, likely an infinite loop on short reads", DisplayTest(location), timeout, current_split);
+            },
+            // the test itself panicked, e.g. because of invalid input
+            Err(mpsc::RecvTimeoutError::Disconnected) => match thread.join() {
+                Ok(()) => unreachable!("the test thread exited without sending the result"),
+                Err(unwind) => resume_unwind(unwind),
+            },
+        }
+    }
+
+    /// Runs the test, returning the failure instead of panicking.
+    ///
+    /// Only the panic of the breaking pass is printed by the panic hook as usual, panics of the
+    /// other runs, e.g. the ones searching for the split position, are suppressed and only the
+    /// final report is returned.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn try_run<F>(&self, f: F) -> Result<ReadSummary, ReadFailure> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        self.run_no_panic(f).map_err(ReadFailure)
+    }
+
+    /// Runs the closure once with a reader returning chunks of sizes given by `schedule`.
+    ///
+    /// Each `read` call returns at most the next chunk size from the schedule, limited by the
+    /// buffer length and the remaining input. Once the schedule is exhausted the remaining input
+    /// is returned byte-by-byte. No search is performed, this is meant for deterministic
+    /// reproduction of failures, e.g. ones found by fuzzing.
+    ///
+    /// Panics if the schedule contains zeros or its sum exceeds the input length.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run_schedule<F>(self, schedule: &[usize], f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        let location = backtrace_impl::test_location();
+        assert!(!schedule.contains(&0), "invalid read schedule {:?}: chunk sizes must be non-zero", schedule);
+        let sum = schedule.iter().fold(0usize, |sum, chunk| sum.saturating_add(*chunk));
+        assert!(sum <= self.input.len(), "invalid read schedule {:?}: chunk sizes sum to {} but the input has only {} bytes", schedule, sum, self.input.len());
+        let last_method = LastMethod::new();
+        let (input, config) = (self.input, &self.config);
+        if let Err(unwind) = catch_unwind(|| f(TestReader::scheduled(input, config, &last_method, schedule))) {
+            let details = match last_method.take_read() {
+                Some(read) => format!("\nlast read: {}", read),
+                None => String::new(),
+            };
+            match error::get_panic_message(&unwind) {
+                Some(msg) => panic!("{} failed with read schedule {:?}: {}{}", DisplayTest(location), schedule, msg, details),
+                None => resume_unwind(unwind),
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn run_no_panic<F>(&self, f: F) -> Result<ReadSummary, Error> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        self.run_no_panic_with(f, None)
+    }
+
+    /// Tests that the closure propagates read errors of the given kind, panicking if it doesn't.
+    ///
+    /// The closure is called with a reader returning the data byte-by-byte and an error of `kind`
+    /// instead of data once it reaches a position, for each position from the beginning to the
+    /// end of the input. Whenever the error was returned the closure has to return an error of
+    /// the same kind, otherwise the test fails with the position reporting that the error kind
+    /// was mapped or swallowed. This catches consumers handling some kinds of errors specially
+    /// and getting it wrong.
+    ///
+    /// Panics if `kind` is `Interrupted` since correct consumers retry such reads.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run_errors<T, F>(self, kind: io::ErrorKind, f: F) where F: Fn(TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        let location = backtrace_impl::test_location();
+        assert!(kind != io::ErrorKind::Interrupted, "injecting `Interrupted` errors makes no sense, consumers are supposed to retry the read");
+        let (input, config) = (self.input, &self.config);
+        for pos in 0..=input.len() {
+            let last_method = LastMethod::new();
+            let injection = Injection { pos, kind, injected: AssertUnwindSafe(Cell::new(None)), };
+            let result = catch_unwind(|| f(TestReader::injecting(input, config, &last_method, &injection)).map(drop));
+            // the closure may finish or fail before reaching the position
+            let injected = injection.injected.get().unwrap_or(pos);
+            let got = match result {
+                Ok(Err(error)) if error.kind() == kind => continue,
+                Ok(Err(error)) => format!("{:?}", error.kind()),
+                Ok(Ok(())) if injection.injected.get().is_some() => "Ok".to_owned(),
+                Ok(Ok(())) => continue,
+                Err(unwind) => match error::get_panic_message(&unwind) {
+                    Some(msg) => panic!("{} failed when an error was injected at position {}: {}", DisplayTest(location), injected, msg),
+                    None => resume_unwind(unwind),
+                },
+            };
+            panic!("{} failed when an error was injected at position {}: error kind was mapped/swallowed: expected {:?}, got {}", DisplayTest(location), injected, kind, got);
+        }
+    }
+
+    /// Runs the test on multiple threads, panicking if it fails.
+    ///
+    /// This works just like [`run`](Self::run) but the split positions are tried on multiple
+    /// threads which saves a lot of time for large inputs. The smallest failing position is
+    /// reported, same as in the single-threaded search. Once it's found the closure is called
+    /// with it once more on the current thread to get the failure details so the reported culprit
+    /// always belongs to the reported position.
+    ///
+    /// Requires `parallel` feature and Rust 1.63.
+    #[cfg(feature = "parallel")]
+    #[clippy::msrv = "1.63"]
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run_parallel<F>(self, f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe + Send + Sync {
+        let config = &self.config;
+        let find = |input: Input<'_>| error::find_parallel(input.len(), |pos| {
+            let last_method = LastMethod::new();
+            let mut backtrace = None;
+            let backtrace_mut = BacktraceStorageMut::from_mut(&mut backtrace);
+            catch_unwind(|| f(TestReader::searching(input, config, &last_method, pos, backtrace_mut))).is_ok()
+        });
+        if let Err(error) = self.run_no_panic_with(&f, Some(&find)) {
+            self.reporter.on_read_failure(ReadFailure(error));
+        }
+    }
+
+    /// Runs the test finding the failing position using `find` if provided.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn run_no_panic_with<F>(&self, f: F, find: Option<FindPosition<'_>>) -> Result<ReadSummary, Error> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        let input = self.input;
+        let config = &self.config;
+        let location = backtrace_impl::test_location();
+        if input.len() < 2 {
+            panic!("Testing slices shorter than 2 bytes doesn't make sense");
+        }
+        let last_method = LastMethod::new();
+        let last_method = &last_method;
+        if let Some(pos) = forced_position(input.len()) {
+            test_forced(input, config, last_method, location, pos, &f)?;
+            return Ok(ReadSummary {
+                breaking_pass_passed: false,
+                positions_tried: 1,
+                first_reads_tried: 0,
+            });
+        }
+        let positions_tried = match test_splits(input, config, last_method, location, find, &f) {
+            Ok(positions_tried) => positions_tried,
+            Err(mut error) => {
+                if config.shrink_input && error.failure_info.is_some() {
+                    if let Some(shrunk) = error::quiet_panics(|| shrink(input, config, last_method, location, find, &f)) {
+                        error = shrunk;
+                    }
+                }
+                return Err(error);
+            },
+        };
+        if !config.allow_unused_reader && last_method.take().is_none() {
+            return Err(Error::new(UnlocalizedFailure::unwind("the test closure never read from the provided reader - the test is not exercising anything"), Operation::Read, location, config.normalize_message));
+        }
+        let mut summary = ReadSummary {
+            breaking_pass_passed: !config.skip_breaking,
+            positions_tried,
+            first_reads_tried: 0,
+        };
+        if config.sweep_first_read {
+            let failure = error::quiet_panics(|| (2..input.len()).find_map(|first_chunk| {
+                summary.first_reads_tried += 1;
+                last_method.take();
+                catch_unwind(|| f(TestReader::greedy(input, config, last_method, first_chunk))).err().map(|unwind| (first_chunk, unwind))
+            }));
+            if let Some((first_chunk, unwind)) = failure {
+                return Err(Error {
+                    first_chunk: Some(first_chunk),
+                    transition: last_method.take_transition(),
+                    last_method: last_method.take(),
+                    ..Error::new(unwind, Operation::Read, location, config.normalize_message)
+                });
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Finds the smallest failing split position of the given input without reporting details.
+type FindPosition<'a> = &'a (dyn Fn(Input<'_>) -> Option<usize> + 'a);
+
+/// Runs the breaking pass and if it fails searches for the split position causing the failure.
+///
+/// If `find` is provided the search only runs for the position it returned. On success returns
+/// the number of split positions exercised by the breaking pass or tried in place of it.
+fn test_splits<F>(input: Input<'_>, config: &Config, last_method: &LastMethod, location: TestLocation, find: Option<FindPosition<'_>>, f: &F) -> Result<usize, Error> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+    last_method.take();
+    let mut positions_tried = 0;
+    let first_pass = if config.skip_breaking {
+        // the first failing split takes the place of the breaking pass
+        let first_failure = error::quiet_panics(|| {
+            let failure_at = |pos| {
+                positions_tried += 1;
+                last_method.take();
+                let mut backtrace = None;
+                let backtrace_mut = BacktraceStorageMut::from_mut(&mut backtrace);
+                catch_unwind(|| f(TestReader::searching(input, config, last_method, pos, backtrace_mut))).err()
+            };
+            match find {
+                Some(find) => find(input).into_iter().find_map(failure_at),
+                None => search_positions(input.len(), config).find_map(failure_at),
+            }
+        });
+        first_failure.map_or(Ok(()), Err)
+    } else {
+        last_method.take_splits();
+        let result = catch_unwind(|| f(TestReader::breaking(input, config, last_method)));
+        positions_tried = last_method.take_splits();
+        result
+    };
+    if let Err(unwind) = first_pass {
+        let breaking_transition = last_method.take_transition();
+        let breaking_method = last_method.take();
+        let attempt = |pos, backtrace_mut: BacktraceStorageMut<'_>| {
+            last_method.take();
+            catch_unwind(|| f(TestReader::searching(input, config, last_method, pos, backtrace_mut)))
+        };
+        // `read_to_end` is never split so the failure can not be caused by splitting, searching
+        // would only waste time and possibly report a misleading position
+        let unsplit = breaking_transition.is_none() && breaking_method == Some(Method::ReadToEnd);
+        let mut failure_info = error::quiet_panics(|| match find {
+            _ if unsplit => None,
+            Some(find) => error::search_in(find(input), attempt),
+            None => error::search_in(search_positions(input.len(), config), attempt),
+        });
+        if let Some(info) = &mut failure_info {
+            info.last_read = last_method.take_read();
+            info.segment_boundary = config.segment_boundaries.iter().position(|boundary| *boundary == info.pos);
+        }
+        // the method is reported from the same run as the position
+        let (transition, last_method) = match failure_info {
+            Some(_) => (last_method.take_transition(), last_method.take()),
+            None => (breaking_transition, breaking_method),
+        };
+        // Several positions may reproduce the same failure, e.g. if multiple reads are
+        // mishandled, or fail differently if there are multiple bugs. The first one found is
+        // reported but knowing about the others is useful too. `find` may skip positions so this
+        // is done for the full search only.
+        if let (Some(info), None) = (&mut failure_info, find) {
+            let normalize = config.normalize_message;
+            // a different message means there are likely two bugs, so the other one is localized too
+            let breaking_differs = !error::same_message(&unwind, &info.unwind, normalize);
+            // each position gets its own backtrace since different positions may implicate
+            // different call sites
+            let failure_at = |pos: usize| (0..config.position_attempts).find_map(|_| {
+                let calls = LastMethod::new();
+                let mut backtrace = None;
+                let backtrace_mut = BacktraceStorageMut::from_mut(&mut backtrace);
+                catch_unwind(|| f(TestReader::searching(input, config, &calls, pos, backtrace_mut))).err().map(|failure| (failure, backtrace))
+            });
+            // the positions the search would try after the reported one, each once
+            let reported = info.pos;
+            let mut later = search_positions(input.len(), config).step_by(config.position_attempts).skip_while(move |pos| *pos != reported).skip(1);
+            if config.all_failures {
+                let mut stats = FailureStats::new(error::normalize_message(error::get_panic_message(&info.unwind), normalize).as_deref());
+                let mut breaking_pos = None;
+                let mut later_backtraces = Vec::new();
+                info.also_reproduced = error::quiet_panics(|| later.filter(|pos| match failure_at(*pos) {
+                    Some((failure, mut backtrace)) => {
+                        if breaking_differs && breaking_pos.is_none() && error::same_message(&failure, &unwind, normalize) {
+                            breaking_pos = Some(*pos);
+                        }
+                        // resolving is expensive so only the backtraces of failures are resolved
+                        backtrace_impl::resolve(&mut backtrace);
+                        later_backtraces.push(backtrace);
+                        stats.record(*pos, error::normalize_message(error::get_panic_message(&failure), normalize).as_deref())
+                    },
+                    None => false,
+                }).collect());
+                info.stats = Some(stats);
+                info.breaking_pos = breaking_pos;
+                info.later_backtraces = later_backtraces;
+            } else if breaking_differs {
+                info.breaking_pos = error::quiet_panics(|| later.find(|pos| match failure_at(*pos) {
+                    Some((failure, _)) => error::same_message(&failure, &unwind, normalize),
+                    None => false,
+                }));
+            }
+        }
+        return Err(Error {
+            failure_info,
+            last_method,
+            transition,
+            ..Error::new(unwind, Operation::Read, location, config.normalize_message)
+        });
+    }
+    Ok(positions_tried)
+}
+
+/// Returns the split positions of input with length `len` in the order they are searched.
+fn search_positions(len: usize, config: &Config) -> impl Iterator<Item = usize> + '_ {
+    let positions: Box<dyn Iterator<Item = usize>> = match (config.search_from, config.search_reverse) {
+        (Some(start), _) => Box::new(error::positions_around(len, start)),
+        (None, false) => Box::new(1..len),
+        (None, true) => Box::new((1..len).rev()),
+    };
+    let boundaries = &config.segment_boundaries;
+    let positions = positions.filter(move |pos| !boundaries.contains(pos));
+    let attempts = config.position_attempts;
+    boundaries.iter().copied().chain(positions).flat_map(move |pos| std::iter::repeat(pos).take(attempts))
+}
+
+/// Returns the split position requested by `IO_CHECK_FORCE_POS` environment variable if valid.
+///
+/// Invalid values are reported and ignored so that a typo doesn't make the test pass silently.
+/// The variable is meant to be combined with a test name filter but other tests may still run,
+/// so a position out of range is reported only once per process to avoid flooding the output.
+fn forced_position(len: usize) -> Option<usize> {
+    static OUT_OF_RANGE: Once = Once::new();
+
+    let value = std::env::var("IO_CHECK_FORCE_POS").ok()?;
+    match value.parse() {
+        Ok(pos) if pos > 0 && pos < len => Some(pos),
+        Ok(pos) => {
+            OUT_OF_RANGE.call_once(|| eprintln!("warning: ignoring IO_CHECK_FORCE_POS={} out of range for input of length {}, the split position must be between 1 and {} (reported once)", pos, len, len - 1));
+            None
+        },
+        Err(_) => {
+            eprintln!("warning: ignoring invalid IO_CHECK_FORCE_POS={:?}, the split position must be a number", value);
+            None
+        },
+    }
+}
+
+/// Runs the closure only with the input split at `pos`, skipping the breaking pass and search.
+fn test_forced<F>(input: Input<'_>, config: &Config, last_method: &LastMethod, location: TestLocation, pos: usize, f: &F) -> Result<(), Error> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+    let attempt = |pos, backtrace_mut: BacktraceStorageMut<'_>| {
+        last_method.take();
+        catch_unwind(|| f(TestReader::searching(input, config, last_method, pos, backtrace_mut)))
+    };
+    let mut backtrace = None;
+    let unwind = match attempt(pos, BacktraceStorageMut::from_mut(&mut backtrace)) {
+        Ok(()) => return Ok(()),
+        Err(unwind) => unwind,
+    };
+    // the closure is pure so running it again reproduces the failure and collects the details
+    let mut failure_info = error::quiet_panics(|| error::search_in(Some(pos), attempt));
+    if let Some(info) = &mut failure_info {
+        info.last_read = last_method.take_read();
+    }
+    Err(Error {
+        failure_info,
+        transition: last_method.take_transition(),
+        last_method: last_method.take(),
+        ..Error::new(unwind, Operation::Read, location, config.normalize_message)
+    })
+}
+
+/// Finds the shortest prefix of `input` that passes unsplit but fails at a split position.
+fn shrink<F>(input: Input<'_>, config: &Config, last_method: &LastMethod, location: TestLocation, find: Option<FindPosition<'_>>, f: &F) -> Option<Error> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+    (2..input.len()).find_map(|len| {
+        let prefix = input.prefix(len);
+        catch_unwind(|| f(TestReader::greedy(prefix, config, last_method, len))).ok()?;
+        match test_splits(prefix, config, last_method, location, find, f) {
+            Err(error) if error.failure_info.is_some() => Some(Error { shrunk_input: Some(prefix.bytes(0, len).into_owned()), ..error }),
+            _ => None,
+        }
+    })
+}
+
+/// Information about a successful read test.
+///
+/// Returned by [`try_test_read`] and [`ReadTest::try_run`] so that it's possible to check how
+/// thoroughly the closure was tested.
+///
+/// If the split position is forced using `IO_CHECK_FORCE_POS` a passing test returns a summary
+/// with `breaking_pass_passed` being `false` and `positions_tried` being 1 since the closure was
+/// only called with the forced position.
+///
+/// [`try_test_read`]: super::try_test_read
+#[derive(Debug, Clone)]
+pub struct ReadSummary {
+    breaking_pass_passed: bool,
+    positions_tried: usize,
+    first_reads_tried: usize,
+}
+
+impl ReadSummary {
+    /// Returns `true` if the closure passed when reading byte-by-byte on the first try.
+    ///
+    /// The search is only run if this pass fails so `true` means no search happened. It's `false`
+    /// if the pass was skipped using [`ReadTest::skip_breaking`].
+    pub fn breaking_pass_passed(&self) -> bool {
+        self.breaking_pass_passed
+    }
+
+    /// Returns the number of split positions the closure was exercised with.
+    ///
+    /// In the breaking pass these are the interior positions at which a read returned less data
+    /// than requested. A closure reading the whole input using `read` exercises all of them, so
+    /// comparing this with the length of the input minus one shows whether the test covered the
+    /// split space. Reads done by `read_exact` and `read_to_end` are not split so they don't
+    /// count. If the pass was replaced using [`ReadTest::skip_breaking`] this is the number of
+    /// positions the closure was called with.
+    pub fn positions_tried(&self) -> usize {
+        self.positions_tried
+    }
+
+    /// Returns the number of sizes of the first read tried by [`ReadTest::sweep_first_read`].
+    ///
+    /// It's zero if the sweep wasn't enabled.
+    pub fn first_reads_tried(&self) -> usize {
+        self.first_reads_tried
+    }
+}
+
+/// Failure of a read test.
+///
+/// Returned by [`try_test_read`] and [`ReadTest::try_run`] instead of panicking.
+///
+/// [`try_test_read`]: super::try_test_read
+#[derive(Debug)]
+pub struct ReadFailure(Error);
+
+impl ReadFailure {
+    /// Returns the split position at which the closure failed if it was found.
+    pub fn position(&self) -> Option<usize> {
+        self.0.failure_info.as_ref().map(|info| info.pos)
+    }
+
+    /// Returns all split positions at which the closure failed, starting with the reported one.
+    ///
+    /// If [`ReadTest::find_all_failures`] was enabled the remaining positions are tried after
+    /// finding the reported one, so this shows the whole failure surface, e.g. to confirm that a
+    /// fix closed the bug at all boundaries. The failures may be caused by different bugs. The
+    /// positions are in the order they were searched.
+    ///
+    /// The list is empty if no position was found and contains only the reported position if
+    /// `find_all_failures` wasn't enabled or the search was run by `ReadTest::run_parallel`
+    /// which doesn't try all positions.
+    pub fn all_failures(&self) -> Vec<usize> {
+        self.0.failure_info.iter().flat_map(|info| {
+            let later = info.stats.iter().flat_map(|stats| stats.later_positions.iter().copied());
+            std::iter::once(info.pos).chain(later)
+        }).collect()
+    }
+
+    /// Returns the backtraces of all failures along with their split positions.
+    ///
+    /// The positions are the same as returned by [`all_failures`](Self::all_failures) and each
+    /// backtrace is formatted the same way as in the failure message, starting with the most
+    /// likely culprit. Comparing them shows whether different positions implicate different
+    /// call sites. Backtraces are only available if the crate was compiled with a feature
+    /// enabling them, the text explains it otherwise.
+    ///
+    /// Resolving backtraces is expensive, so the later ones are only resolved if
+    /// [`ReadTest::find_all_failures`] was enabled, otherwise only the reported one is returned.
+    pub fn all_failure_backtraces(&self) -> Vec<(usize, String)> {
+        let operation = self.0.operation;
+        self.0.failure_info.iter().flat_map(|info| {
+            let first = std::iter::once((info.pos, &info.backtrace));
+            let later = info.stats.iter().flat_map(|stats| stats.later_positions.iter().copied()).zip(&info.later_backtraces);
+            first.chain(later).map(move |(pos, backtrace)| (pos, DisplayBacktrace::new(backtrace, operation).to_string()))
+        }).collect()
+    }
+
+    /// Returns the shortest prefix of the input reproducing the failure.
+    ///
+    /// This is only available if [`ReadTest::shrink_input`] was enabled and a shorter input was
+    /// found. [`position`](Self::position) refers to this input then.
+    pub fn shrunk_input(&self) -> Option<&[u8]> {
+        self.0.shrunk_input.as_deref()
+    }
+
+    /// Returns the message of the panic caught in the first pass if it was a string.
+    pub fn message(&self) -> Option<&str> {
+        error::get_panic_message(&self.0.unwind)
+    }
+
+    /// Returns the payload of the panic caught in the first pass.
+    ///
+    /// This allows downcasting panics with custom payload types, e.g. raised by a test framework
+    /// using `std::panic::panic_any`, which [`message`](Self::message) can not present.
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        &*self.0.unwind
+    }
+
+    /// Returns the payload of the panic caught in the first pass, consuming the failure.
+    ///
+    /// The payload can be passed to `std::panic::resume_unwind` to continue unwinding with it.
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.0.unwind
+    }
+
+    /// Panics with the same message [`test_read`] would.
+    ///
+    /// [`test_read`]: super::test_read
+    pub fn panic(self) -> ! {
+        self.0.panic()
+    }
+
+    /// Marks the failure as found on the read side of `test_copy`.
+    pub(crate) fn copy_side(mut self) -> Self {
+        self.0.copy_side = true;
+        self
+    }
+}
+
+/// Formats the same message [`panic`](Self::panic) uses, including the culprit backtrace.
+impl fmt::Display for ReadFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Runs `f` with a reader returning whole reads, used to test the other side of `test_copy`.
+pub(crate) fn read_whole<F>(input: &[u8], f: F) where F: FnOnce(TestReader<'_>) {
+    let test = ReadTest::new(input);
+    let last_method = LastMethod::new();
+    f(TestReader::greedy(test.input, &test.config, &last_method, input.len()))
+}
+
+// we want proper doc at top-level of the crate
+pub(crate) mod hack {
+    use super::*;
+
+    /// Tests whether the closure correctly handles split reads.
+    ///
+    /// This is the entry point of this crate for read testing.
+    /// You provide `input` that will be returned from reader and a closure that accepts the reader and
+    /// uses it to read the input. The closure should check if the decoded values equal to the expected
+    /// values and *panic* if not. (e.g. using `assert_eq!()`)
+    ///
+    /// For best results make sure no other inputs affect the test - the function should be pure.
+    /// It will be called once and if it panics it'll be called again multiple times with
+    /// differently-behaving readers.
+    ///
+    /// With `rust_1_46` feature the failure message contains the location of this call.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read<F>(input: &[u8], f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run(f)
+    }
+
+    /// Checks that the closure passes with a reader that doesn't split reads.
+    ///
+    /// The closure is called once with a reader returning as much data as requested, just like
+    /// `std::io::Cursor` would. If it fails even then the bug is unrelated to splitting and the
+    /// panic message says so. Running this before [`test_read`] with the same closure (pass it
+    /// by reference) disambiguates "the code is broken regardless" from "the code mishandles
+    /// split reads".
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn sanity_read<F>(input: &[u8], f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        let location = backtrace_impl::test_location();
+        if let Err(unwind) = catch_unwind(|| read_whole(input, f)) {
+            match error::get_panic_message(&unwind) {
+                Some(msg) => panic!("{} failed even with a reader returning whole reads, the bug is unrelated to splitting: {}", DisplayTest(location), msg),
+                None => resume_unwind(unwind),
+            }
+        }
+    }
+
+    /// Tests whether the closure propagates read errors of the given kind.
+    ///
+    /// The reader returns an error of `kind` instead of data at each position in turn and the
+    /// closure has to return an error of the same kind. See [`ReadTest::run_errors`] for details.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_errors<T, F>(input: &[u8], kind: io::ErrorKind, f: F) where F: Fn(TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run_errors(kind, f)
+    }
+
+    /// Tests whether the closure reports truncated input as `UnexpectedEof`.
+    ///
+    /// Only the first `truncate_to` bytes of `input` are provided to the closure, which has to
+    /// return an error of kind `UnexpectedEof`, e.g. propagated from `read_exact`. Returning `Ok`
+    /// means the decoder swallowed the EOF and silently produced a partial value, returning an
+    /// error of a different kind means it was mapped, both fail the test. The truncated input is
+    /// split just like in [`test_read`] if it's at least two bytes long, otherwise the closure is
+    /// called once with a reader returning whole reads.
+    ///
+    /// Decoders legitimately accepting shorter input, e.g. ones decoding a sequence of items
+    /// until EOF, pass `Ok` only if `truncate_to` cuts an item in half, so choose it accordingly.
+    ///
+    /// Panics if `truncate_to` is not less than the length of `input`.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_short<T, F>(input: &[u8], truncate_to: usize, f: F) where F: Fn(TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        let location = backtrace_impl::test_location();
+        assert!(truncate_to < input.len(), "the input must be truncated to fewer than {} bytes, {} given", input.len(), truncate_to);
+        let input = &input[..truncate_to];
+        let expect_eof = |reader: TestReader<'_>| match f(reader) {
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => (),
+            Err(error) => panic!("expected `UnexpectedEof` error for input truncated to {} bytes, got {:?}: {}", truncate_to, error.kind(), error),
+            Ok(_) => panic!("the closure returned `Ok` for input truncated to {} bytes, the EOF was swallowed", truncate_to),
+        };
+        if input.len() < 2 {
+            if let Err(unwind) = catch_unwind(|| read_whole(input, expect_eof)) {
+                match error::get_panic_message(&unwind) {
+                    Some(msg) => panic!("{} failed: {}", DisplayTest(location), msg),
+                    None => resume_unwind(unwind),
+                }
+            }
+        } else {
+            ReadTest::new(input).run(expect_eof)
+        }
+    }
+
+    /// Tests whether the closure correctly handles split reads, generating the input.
+    ///
+    /// This works just like [`test_read`] but instead of storing the whole input `generator` is
+    /// called with the offset of each read byte and returns the byte at that offset. This is
+    /// useful for testing decoders of large synthetic streams. `generator` must be pure
+    /// (deterministic), the closure is called multiple times and the split search only makes
+    /// sense if it gets the same data every time.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_gen<G, F>(len: usize, generator: G, f: F) where G: Fn(usize) -> u8 + RefUnwindSafe + Sync, F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        ReadTest::generated(len, &generator).run(f)
+    }
+
+    /// Tests whether the closure correctly handles split reads allowing it to return errors.
+    ///
+    /// This works just like [`test_read`] but the closure returns `io::Result` so that `?` can be
+    /// used inside it. See [`ReadTest::run_fallible`] for more information.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_fallible<T, F>(input: &[u8], f: F) where F: Fn(TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run_fallible(f)
+    }
+
+    /// Tests whether the closure correctly handles split reads without panicking on failure.
+    ///
+    /// This works just like [`test_read`] but returns the failure so that it can be inspected.
+    /// On success it returns a summary of the test.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn try_test_read<F>(input: &[u8], f: F) -> Result<ReadSummary, ReadFailure> where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).try_run(f)
+    }
+
+    /// Tests whether decoding correctly handles split reads by comparing the decoded value.
+    ///
+    /// This is a shorthand for [`test_read`] with the comparison performed by this crate so the
+    /// closure only has to decode the value. If the closure returns an error it's reported as an
+    /// unexpected IO error, distinct from a wrong value.
+    ///
+    /// If the pretty `Debug` output of the values spans multiple lines, e.g. for structs with
+    /// many fields, only the first differing line is reported along with a few lines above it
+    /// instead of both whole values.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_eq<T, F>(input: &[u8], expected: T, f: F) where T: PartialEq + fmt::Debug + RefUnwindSafe, F: Fn(TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run(|reader| {
+            match f(reader) {
+                Ok(decoded) if decoded == expected => (),
+                Ok(decoded) => {
+                    let (decoded_lines, expected_lines) = (format!("{:#?}", decoded), format!("{:#?}", expected));
+                    if decoded_lines.contains('\n') || expected_lines.contains('\n') {
+                        panic!("decoded value differs from the expected one, {}", DebugDiff::new(&expected_lines, &decoded_lines));
+                    }
+                    panic!("decoded {:?}, expected {:?}", decoded, expected);
+                },
+                Err(error) => panic!("decoding returned an unexpected IO error: {}", error),
+            }
+        })
+    }
+
+    /// Tests whether a `Read` implementation correctly handles split reads of its source.
+    ///
+    /// This is the dual of [`test_read`]: instead of testing a consumer of `Read` it tests a reader
+    /// built on top of another reader, e.g. a decompressor. `make_reader` accepts the source
+    /// returning `input` in chunks and wraps it in the reader being tested. The whole output of
+    /// the wrapping reader is then read and compared to `expected_output`.
+    ///
+    /// The reader is boxed because it borrows the source.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_reader_impl<F>(input: &[u8], expected_output: &[u8], make_reader: F) where F: for<'a> Fn(TestReader<'a>) -> Box<dyn Read + 'a> + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run(|source| {
+            let mut output = Vec::new();
+            if let Err(error) = make_reader(source).read_to_end(&mut output) {
+                panic!("reading from the tested reader failed: {}", error);
+            }
+            assert!(output == expected_output, "the tested reader produced unexpected output\n{}", HexDiff::new(expected_output, &output, 0));
+        })
+    }
+
+    /// Tests whether decoding a stream of values correctly handles split reads.
+    ///
+    /// `decode_one` is called repeatedly with the same reader, once for each value in `expected`,
+    /// and each decoded value is compared to the corresponding expected one. On mismatch the
+    /// index of the value is reported along with the split position, which helps with streams of
+    /// records where only one of them is affected by the split.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_iter<T, F>(input: &[u8], expected: &[T], decode_one: F) where T: PartialEq + fmt::Debug + RefUnwindSafe, F: Fn(&mut TestReader<'_>) -> io::Result<T> + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run(|mut reader| {
+            for (index, expected) in expected.iter().enumerate() {
+                match decode_one(&mut reader) {
+                    Ok(decoded) if decoded == *expected => (),
+                    Ok(decoded) => {
+                        let message = format!("decoded {:?}, expected {:?}", decoded, expected);
+                        DecodedMismatch { index: Some(index), message, }.panic()
+                    },
+                    Err(error) => panic!("decoding value #{} returned an unexpected IO error: {}", index, error),
+                }
+            }
+        })
+    }
+
+    /// Tests whether the closure correctly handles split reads of concatenated segments.
+    ///
+    /// The closure reads the concatenation of `segments`, e.g. header bytes followed by body
+    /// bytes. Since bugs of code stitching buffers together tend to hide at the joins, the split
+    /// positions at the segment boundaries are tried first and the failure message says which
+    /// boundary the position corresponds to. See [`ReadTest::segment_boundaries`] for more
+    /// information.
+    ///
+    /// Panics if any of the segments is empty since it would have no boundary of its own.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_segments<F>(segments: &[&[u8]], f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        if let Some(index) = segments.iter().position(|segment| segment.is_empty()) {
+            panic!("segment #{} is empty", index);
+        }
+        let input = segments.concat();
+        let boundaries = segments.iter().scan(0, |end, segment| {
+            *end += segment.len();
+            Some(*end)
+        }).filter(|end| *end < input.len()).collect::<Vec<_>>();
+        ReadTest::new(&input).segment_boundaries(&boundaries).run(f)
+    }
+
+    /// Tests the closure with a reader returning chunks of sizes given by `schedule`.
+    ///
+    /// This is a shorthand for [`ReadTest::run_schedule`], see it for more information.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_schedule<F>(input: &[u8], schedule: &[usize], f: F) where F: Fn(TestReader<'_>) + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run_schedule(schedule, f)
+    }
+
+    /// Tests whether decoding correctly handles split reads using a predicate to check the value.
+    ///
+    /// This is useful for types that don't implement `PartialEq` or `Debug`. `decode` accepts the
+    /// reader and returns the decoded value, `check` returns `Err` with the reason if the value is
+    /// wrong. The reason is reported in the same way panics in [`test_read`] are.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_read_check<T, D, C>(input: &[u8], decode: D, check: C) where D: Fn(TestReader<'_>) -> T + UnwindSafe + RefUnwindSafe, C: Fn(&T) -> Result<(), String> + UnwindSafe + RefUnwindSafe {
+        ReadTest::new(input).run(|reader| {
+            if let Err(reason) = check(&decode(reader)) {
+                panic!("{}", reason);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+// the tests intentionally contain buggy IO code
+#[allow(clippy::unused_io_amount)]
+mod tests {
+    use std::io::{self, Read, Seek, SeekFrom};
+    use crate::error::Method;
+    use super::ReadTest;
+    use super::hack::{sanity_read, test_read_check, test_read_eq, test_read_errors, test_read_fallible, test_read_gen, test_read_iter, test_read_schedule, test_read_segments, test_read_short, test_reader_impl, try_test_read};
+
+    #[test]
+    fn basic() {
+        let err = ReadTest::new(&[1, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            let num = u16::from_le_bytes(buf);
+            assert_eq!(num, 1);
+        }).unwrap_err();
+
+        assert!(err.panic_message1().unwrap().contains("65281"));
+        assert_eq!(err.panic_message2(), err.panic_message1());
+        assert_eq!(err.pos().unwrap(), 1);
+    }
+
+    #[test]
+    fn read_exact_followed_by_read() {
+        let err = ReadTest::new(&[1, 0, 1, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            let num = u16::from_le_bytes(buf);
+            assert_eq!(num, 1);
+
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            let num = u16::from_le_bytes(buf);
+            assert_eq!(num, 1);
+        }).unwrap_err();
+
+        assert!(err.panic_message1().unwrap().contains("65281"));
+        assert_eq!(err.panic_message2(), err.panic_message1());
+        assert_eq!(err.pos().unwrap(), 3);
+    }
+
+    #[test]
+    fn no_error() {
+        ReadTest::new(&[1, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            let num = u16::from_le_bytes(buf);
+            assert_eq!(num, 1);
+        }).unwrap();
+
+    }
+
+    #[test]
+    #[should_panic = "consumer did not stop on EOF (read returned 0 repeatedly)"]
+    fn loop_on_eof() {
+        ReadTest::new(&[1, 0]).max_eof_reads(16).run(|mut reader| {
+            let mut buf = [0u8; 4];
+            let mut len = 0;
+            // buggy: never stops on `Ok(0)`
+            while len < buf.len() {
+                len += reader.read(&mut buf[len..]).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn repeated_eof_below_limit() {
+        ReadTest::new(&[1, 0]).max_eof_reads(2).run(|mut reader| {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).unwrap();
+            assert_eq!(reader.read(&mut [0u8; 4]).unwrap(), 0);
+            assert_eq!(reader.read(&mut [0u8; 4]).unwrap(), 0);
+            assert_eq!(buf, [1, 0]);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "rust_1_46")]
+    #[should_panic = "io_check test at src/read.rs:"]
+    fn failure_names_test_location() {
+        super::hack::test_read(&[1, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 1: expected 1, got 65281"]
+    fn check_predicate() {
+        test_read_check(&[1, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            u16::from_le_bytes(buf)
+        }, |num| if *num == 1 { Ok(()) } else { Err(format!("expected 1, got {}", num)) });
+    }
+
+    #[test]
+    #[should_panic = "failed when the first read returned 2 bytes"]
+    fn sweep_first_read() {
+        ReadTest::new(&[1, 0, 0, 0]).sweep_first_read().run(|mut reader| {
+            let mut buf = [0u8; 4];
+            let len = reader.read(&mut buf).unwrap();
+            // buggy: assumes that if more than one byte was read, the whole buffer was filled
+            if len == 1 {
+                reader.read_exact(&mut buf[1..]).unwrap();
+            }
+            assert_eq!(u32::from_le_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    fn sweep_first_read_correct() {
+        ReadTest::new(&[1, 0, 0, 0]).sweep_first_read().run(|mut reader| {
+            let mut buf = [0u8; 4];
+            let mut len = 0;
+            while len < buf.len() {
+                len += reader.read(&mut buf[len..]).unwrap();
+            }
+            assert_eq!(u32::from_le_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    fn debug() {
+        ReadTest::new(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]).run(|mut reader| {
+            reader.read_exact(&mut [0; 2]).unwrap();
+            assert_eq!(format!("{:?}", reader), "TestReader { mode: \"breaking\", consumed: 2, remaining: 9, upcoming: [3, 4, 5, 6, 7, 8, 9, 10, ..] }");
+            reader.read_to_end(&mut Vec::new()).unwrap();
+        });
+    }
+
+    /// Decodes hex digits, two input bytes produce one output byte
+    struct Unhex<R>(R);
+
+    impl<R: Read> Read for Unhex<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let mut digits = [0u8; 2];
+            let len = self.0.read(&mut digits)?;
+            if len == 0 || buf.is_empty() {
+                return Ok(0);
+            }
+            // buggy: doesn't handle reading a single digit
+            let digit = |c: u8| (c as char).to_digit(16).unwrap_or(0) as u8;
+            buf[0] = digit(digits[0]) << 4 | digit(digits[1]);
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn reader_impl() {
+        test_reader_impl(b"hello", b"hell", |source| Box::new(source.take(4)));
+    }
+
+    #[test]
+    #[should_panic = "the tested reader produced unexpected output"]
+    fn reader_impl_short_read() {
+        test_reader_impl(b"2a2b", &[42, 43], |source| Box::new(Unhex(source)));
+    }
+
+    #[test]
+    fn try_summary() {
+        let summary = try_test_read(&[1, 0, 1], |mut reader| {
+            let mut buf = [0u8; 3];
+            reader.read_exact(&mut buf).unwrap();
+        }).unwrap();
+        assert!(summary.breaking_pass_passed());
+        // `read_exact` is not split
+        assert_eq!(summary.positions_tried(), 0);
+
+        let summary = try_test_read(&[1, 0, 1], |mut reader| {
+            let mut buf = [0u8; 3];
+            let mut filled = 0;
+            while filled < buf.len() {
+                filled += reader.read(&mut buf[filled..]).unwrap();
+            }
+        }).unwrap();
+        assert_eq!(summary.positions_tried(), 2);
+        assert_eq!(summary.first_reads_tried(), 0);
+
+        let summary = ReadTest::new(&[1, 0, 1, 0]).sweep_first_read().try_run(|mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf).unwrap();
+        }).unwrap();
+        assert_eq!(summary.positions_tried(), 0);
+        assert_eq!(summary.first_reads_tried(), 2);
+    }
+
+    #[test]
+    fn try_failure() {
+        let failure = try_test_read(&[1, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert_eq!(failure.position(), Some(1));
+        assert!(failure.message().unwrap().contains("65281"));
+    }
+
+    #[test]
+    fn failure_display_matches_panic() {
+        let failure = try_test_read(&[1, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        let displayed = failure.to_string();
+        assert!(displayed.contains("failed at position 1"), "{}", displayed);
+        let unwind = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| failure.panic())).unwrap_err();
+        assert_eq!(unwind.downcast_ref::<String>(), Some(&displayed));
+    }
+
+    #[test]
+    fn skip_breaking() {
+        let consumer = |mut reader: super::TestReader<'_>| {
+            let mut buf = [0u8; 4];
+            let mut filled = 0;
+            let mut reads = 0;
+            while filled < buf.len() {
+                filled += reader.read(&mut buf[filled..]).unwrap();
+                reads += 1;
+            }
+            assert_eq!(buf, [1, 2, 3, 4]);
+            // the read-ahead optimization expects at most one short read
+            assert!(reads <= 2, "too many reads");
+        };
+        assert!(ReadTest::new(&[1, 2, 3, 4]).try_run(consumer).is_err());
+        ReadTest::new(&[1, 2, 3, 4]).skip_breaking().run(consumer);
+        let summary = ReadTest::new(&[1, 2, 3, 4]).skip_breaking().try_run(consumer).unwrap();
+        assert!(!summary.breaking_pass_passed());
+        assert_eq!(summary.positions_tried(), 3);
+    }
+
+    #[test]
+    #[allow(clippy::unused_io_amount)]
+    fn skip_breaking_finds_short_reads() {
+        let err = ReadTest::new(&[1, 0, 2, 0]).skip_breaking().try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: assumes `read` fills the buffer
+            reader.read(&mut buf).unwrap();
+            assert_eq!(buf, [2, 0]);
+        }).unwrap_err();
+        assert_eq!(err.position(), Some(3));
+        assert!(err.to_string().contains("failed at position 3: assertion"));
+    }
+
+    #[test]
+    fn two_bugs() {
+        let displayed = ReadTest::new(&[1, 0, 2, 0]).try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            let first = reader.read(&mut buf).unwrap();
+            reader.read_exact(&mut buf[first..]).unwrap();
+            let second = reader.read(&mut buf).unwrap();
+            reader.read_exact(&mut buf[second..]).unwrap();
+            // buggy: the checks of the amounts are not needed
+            assert!(second == 2, "second read was short");
+            assert!(first == 2, "first read was short");
+        }).unwrap_err().to_string();
+        assert!(displayed.contains("failed with two different messages, likely caused by two bugs\nbug A localized at position 1: first read was short"), "{}", displayed);
+        assert!(displayed.contains("bug B (breaking pass) localized at position 3: second read was short"), "{}", displayed);
+    }
+
+    #[test]
+    fn normalize_messages() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let buggy = |mut reader: super::TestReader<'_>| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            let call = CALLS.fetch_add(1, Ordering::Relaxed);
+            assert!(u16::from_le_bytes(buf) == 1, "corrupted value (call {})", call);
+        };
+        let displayed = ReadTest::new(&[1, 0]).try_run(buggy).unwrap_err().to_string();
+        assert!(displayed.contains("failed with two different messages"), "{}", displayed);
+        let strip_digits = |message: &str| message.chars().filter(|c| !c.is_ascii_digit()).collect();
+        let displayed = ReadTest::new(&[1, 0]).normalize_messages(strip_digits).try_run(buggy).unwrap_err().to_string();
+        assert!(displayed.contains("failed at position 1: corrupted value (call "), "{}", displayed);
+    }
+
+    // run by `forced_position` in a subprocess to set the environment variable
+    #[test]
+    #[ignore]
+    fn forced_position_child() {
+        let failure = try_test_read(&[1, 0, 2, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+        }).unwrap_err();
+        println!("failed at {:?}", failure.position());
+    }
+
+    #[test]
+    fn forced_position() {
+        let run_child = |pos: &str| std::process::Command::new(std::env::current_exe().unwrap())
+            .args(&["read::tests::forced_position_child", "--exact", "--ignored", "--nocapture", "--test-threads=1"])
+            .env("IO_CHECK_FORCE_POS", pos)
+            .output()
+            .unwrap();
+
+        let output = run_child("3");
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("failed at Some(3)"));
+
+        // out of range for this input, the search finds the first bug
+        let output = run_child("4");
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("failed at Some(1)"));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("warning: ignoring IO_CHECK_FORCE_POS=4 out of range for input of length 4, the split position must be between 1 and 3"), "{}", stderr);
+
+        let output = run_child("x");
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("failed at Some(1)"));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("warning: ignoring invalid IO_CHECK_FORCE_POS=\"x\", the split position must be a number"), "{}", stderr);
+    }
+
+    #[test]
+    fn try_all_failures() {
+        let failure = ReadTest::new(&[1, 0, 2, 0, 3]).find_all_failures().try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+            reader.read(&mut buf[..1]).unwrap();
+            assert_eq!(buf[0], 3);
+        }).unwrap_err();
+        assert_eq!(failure.position(), Some(1));
+        // the `read_exact` and the last read are handled correctly
+        assert_eq!(failure.all_failures(), [1]);
+
+        let buggy = |mut reader: super::TestReader<'_>| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+        };
+        let failure = ReadTest::new(&[1, 0, 2, 0]).find_all_failures().try_run(buggy).unwrap_err();
+        assert_eq!(failure.all_failures(), [1, 3]);
+        // the search stops at the first failure by default
+        let failure = try_test_read(&[1, 0, 2, 0], buggy).unwrap_err();
+        assert_eq!(failure.all_failures(), [1]);
+    }
+
+    #[test]
+    fn read_eq() {
+        test_read_eq(&[42, 0], 42, |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).map(|_| u16::from_le_bytes(buf))
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 1: decoded 65322, expected 42"]
+    fn read_eq_mismatch() {
+        test_read_eq(&[42, 0], 42, |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).map(|_| u16::from_le_bytes(buf))
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 4: decoded value differs from the expected one, first difference at line 4 of the debug output (- expected, + received):\n     magic: 1,\n     version: 2,\n-    length: 3,\n+    length: "]
+    fn read_eq_struct_mismatch() {
+        #[derive(Debug, PartialEq)]
+        struct Header {
+            magic: u16,
+            version: u8,
+            length: u32,
+            flags: u8,
+        }
+
+        test_read_eq(&[1, 0, 2, 3, 0, 0, 0, 4], Header { magic: 1, version: 2, length: 3, flags: 4, }, |mut reader| {
+            let mut magic = [0u8; 2];
+            reader.read_exact(&mut magic)?;
+            let mut version = [0u8; 1];
+            reader.read_exact(&mut version)?;
+            let mut length = [0u8; 4];
+            // buggy: assumes `read` fills the buffer
+            reader.read(&mut length)?;
+            let mut flags = [0u8; 1];
+            reader.read_exact(&mut flags)?;
+            Ok(Header { magic: u16::from_le_bytes(magic), version: version[0], length: u32::from_le_bytes(length), flags: flags[0], })
+        });
+    }
+
+    #[test]
+    #[should_panic = "decoding returned an unexpected IO error"]
+    fn read_eq_error() {
+        test_read_eq(&[42, 0], 42, |mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf).map(|_| u32::from_le_bytes(buf))
+        });
+    }
+
+    #[test]
+    #[should_panic = "closure did not terminate within 100ms when reading byte-by-byte, likely an infinite loop on short reads"]
+    fn timeout() {
+        ReadTest::new(&[1, 0]).max_eof_reads(usize::MAX).run_with_timeout(std::time::Duration::from_millis(100), |mut reader| {
+            let mut buf = [0u8; 2];
+            // buggy: retries until the whole buffer is filled
+            while reader.read(&mut buf).unwrap() != buf.len() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 1"]
+    fn timeout_not_reached() {
+        ReadTest::new(&[1, 0]).run_with_timeout(std::time::Duration::from_secs(60), |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    fn eof_latches() {
+        ReadTest::new(&[1, 0]).run(|mut reader| {
+            let mut buf = [0u8; 4];
+            while reader.read(&mut buf).unwrap() > 0 {}
+            let error = reader.read_exact(&mut buf[..1]).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer read after EOF"]
+    fn forbid_read_after_eof() {
+        ReadTest::new(&[1, 0]).forbid_read_after_eof().run(|mut reader| {
+            let mut buf = [0u8; 4];
+            // buggy: treats `Ok(0)` as "try again later"
+            for _ in 0..4 {
+                reader.read(&mut buf).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn forbid_trailing_read_exact_loop() {
+        ReadTest::new(&[1, 0, 2, 0]).forbid_trailing_read().run(|mut reader| {
+            let mut buf = [0u8; 4];
+            let mut pos = 0;
+            while pos < buf.len() {
+                pos += reader.read(&mut buf[pos..]).unwrap();
+            }
+            assert_eq!(buf, [1, 0, 2, 0]);
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer issued a trailing read after the whole input was read"]
+    fn forbid_trailing_read() {
+        ReadTest::new(&[1, 0, 2, 0]).forbid_trailing_read().run(|mut reader| {
+            let mut buf = [0u8; 16];
+            let mut pos = 0;
+            // buggy: reads until EOF even though the length is known
+            loop {
+                match reader.read(&mut buf[pos..]).unwrap() {
+                    0 => break,
+                    read => pos += read,
+                }
+            }
+            assert_eq!(buf[..pos], [1, 0, 2, 0]);
+        });
+    }
+
+    #[test]
+    #[cfg(all(feature = "backtrace", not(miri)))]
+    fn forbid_trailing_read_culprit() {
+        let err = ReadTest::new(&[1, 0]).forbid_trailing_read().run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: checks for EOF even though the length is known
+            assert_eq!(reader.read(&mut [0u8; 1]).unwrap(), 0);
+        }).unwrap_err();
+        // symbol names differ between toolchains so only the capture is checked
+        let info = err.failure_info.unwrap();
+        assert_eq!(info.pos, 1);
+        assert!(info.backtrace.is_some());
+        assert_eq!(err.last_method, Some(Method::Read));
+    }
+
+    #[test]
+    fn fallible() {
+        test_read_fallible(&[1, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf)?;
+            assert_eq!(u16::from_le_bytes(buf), 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[should_panic = "unexpected IO error during test: boom"]
+    fn fallible_io_error() {
+        test_read_fallible(&[1, 0], |mut reader| {
+            reader.read_exact(&mut [0u8; 2])?;
+            Err::<(), _>(std::io::Error::new(std::io::ErrorKind::Other, "boom"))
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer read after EOF"]
+    fn fallible_reader_failure() {
+        ReadTest::new(&[1, 0]).forbid_read_after_eof().run_fallible(|mut reader| {
+            let mut buf = [0u8; 4];
+            while reader.read(&mut buf)? != 0 {}
+            // buggy: reads again after EOF
+            reader.read(&mut buf)
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer did not stop on EOF (read returned 0 repeatedly)"]
+    fn fallible_loop_on_eof() {
+        ReadTest::new(&[1, 0]).max_eof_reads(16).run_fallible(|mut reader| {
+            let mut buf = [0u8; 4];
+            let mut len = 0;
+            // buggy: never stops on `Ok(0)`
+            while len < buf.len() {
+                len += reader.read(&mut buf[len..])?;
+            }
+            Ok(())
+        });
+    }
+
+    fn decode_dyn(reader: &mut dyn Read) -> std::io::Result<(u16, u16)> {
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf)?;
+        let first = u16::from_le_bytes(buf);
+        reader.read(&mut buf)?;
+        Ok((first, u16::from_le_bytes(buf)))
+    }
+
+    #[test]
+    fn dyn_read_keeps_overrides() {
+        let err = ReadTest::new(&[1, 0, 2, 0]).run_no_panic(|mut reader| {
+            let (first, second) = decode_dyn(&mut reader).unwrap();
+            assert_eq!(first, 1);
+            assert_eq!(second, 2);
+        }).unwrap_err();
+        // `read_exact` was not split, only the following `read`
+        assert_eq!(err.pos(), Some(3));
+        assert_eq!(err.last_method, Some(Method::Read));
+
+        ReadTest::new(&[1, 0, 2, 0]).run(|mut reader| {
+            let reader: &mut dyn Read = &mut reader;
+            let mut buf = Vec::new();
+            assert_eq!(reader.read_to_end(&mut buf).unwrap(), 4);
+            assert_eq!(buf, [1, 0, 2, 0]);
+        });
+    }
+
+    fn decode_pairs<R: Read>(mut reader: R) -> io::Result<Vec<u16>> {
+        let mut values = Vec::new();
+        let mut buf = [0u8; 2];
+        loop {
+            match reader.read(&mut buf[..1])? {
+                0 => return Ok(values),
+                _ => reader.read_exact(&mut buf[1..])?,
+            }
+            values.push(u16::from_le_bytes(buf));
+        }
+    }
+
+    #[test]
+    fn injected_errors_propagated() {
+        test_read_errors(&[1, 0, 2, 0], io::ErrorKind::ConnectionReset, |reader| decode_pairs(reader));
+    }
+
+    #[test]
+    #[should_panic = "failed when an error was injected at position 0: error kind was mapped/swallowed: expected ConnectionReset, got InvalidData"]
+    fn injected_errors_mapped() {
+        test_read_errors(&[1, 0, 2, 0], io::ErrorKind::ConnectionReset, |reader| {
+            decode_pairs(reader).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed when an error was injected at position 2: error kind was mapped/swallowed: expected ConnectionReset, got Ok"]
+    fn injected_errors_swallowed() {
+        test_read_errors(&[1, 0, 2, 0], io::ErrorKind::ConnectionReset, |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf)?;
+            // buggy: treats any error as the end of optional data
+            Ok(decode_pairs(reader).unwrap_or_default())
+        });
+    }
+
+    fn decode_u32<R: Read>(mut reader: R) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    #[test]
+    fn short_input() {
+        test_read_short(&[1, 0, 2, 0], 3, |reader| decode_pairs(reader));
+        test_read_short(&[1, 0, 0, 0], 2, |reader| decode_u32(reader));
+    }
+
+    #[test]
+    fn short_input_empty() {
+        test_read_short(&[1, 0, 0, 0], 0, |reader| decode_u32(reader));
+        test_read_short(&[1, 0, 0, 0], 1, |reader| decode_u32(reader));
+    }
+
+    #[test]
+    #[should_panic = "the closure returned `Ok` for input truncated to 2 bytes, the EOF was swallowed"]
+    fn short_input_swallowed() {
+        // the sequence legitimately ends after the first item
+        test_read_short(&[1, 0, 2, 0], 2, |reader| decode_pairs(reader));
+    }
+
+    #[test]
+    #[should_panic = "failed: the closure returned `Ok` for input truncated to 0 bytes, the EOF was swallowed"]
+    fn short_input_empty_swallowed() {
+        test_read_short(&[1, 0, 2, 0], 0, |reader| decode_pairs(reader));
+    }
+
+    #[test]
+    #[should_panic = "expected `UnexpectedEof` error for input truncated to 3 bytes, got InvalidData"]
+    fn short_input_mapped() {
+        test_read_short(&[1, 0, 0, 0], 3, |reader| {
+            decode_u32(reader).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        });
+    }
+
+    #[test]
+    #[should_panic = "the input must be truncated to fewer than 4 bytes, 4 given"]
+    fn short_input_not_truncated() {
+        test_read_short(&[1, 0, 0, 0], 4, |reader| decode_u32(reader));
+    }
+
+    #[test]
+    fn custom_poison() {
+        ReadTest::new(&[1, 2, 3]).poison_with(|byte, offset| byte ^ offset as u8 ^ 0x80).run(|mut reader| {
+            let mut buf = [0u8; 3];
+            let mut pos = 0;
+            while pos < 3 {
+                let read = reader.read(&mut buf[pos..]).unwrap();
+                pos += read;
+                if pos < 3 {
+                    let expected = [1, 2, 3][pos] ^ pos as u8 ^ 0x80;
+                    assert_eq!(buf[pos], expected);
+                }
+            }
+            assert_eq!(buf, [1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn no_poison() {
+        // buggy: the buffer happens to contain the expected data already
+        let stale_buffer = |mut reader: super::TestReader<'_>| {
+            let mut buf = [1u8, 0];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        };
+        assert!(ReadTest::new(&[1, 0]).try_run(stale_buffer).is_err());
+        ReadTest::new(&[1, 0]).no_poison().run(stale_buffer);
+
+        // the stale zero differs from the input so the bug is found without poisoning
+        let err = ReadTest::new(&[1, 2]).no_poison().run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes the buffer was filled
+            reader.read(&mut buf).unwrap();
+            assert_eq!(buf, [1, 2]);
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+    }
+
+    #[test]
+    fn custom_poison_detects_bug() {
+        // the second byte is a length that must be at most 0x7f, negating 0xff makes it valid
+        let err = ReadTest::new(&[1, 0xff]).poison_with(|_, _| 0x80).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes the buffer was filled
+            reader.read(&mut buf).unwrap();
+            assert!(buf[1] < 0x80 || buf[1] == 0xff, "invalid length");
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+    }
+
+    #[test]
+    fn on_split_observes_splits() {
+        use std::sync::{Arc, Mutex};
+
+        let splits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&splits);
+        ReadTest::new(&[1, 2, 3]).on_split(move |pos, requested| recorded.lock().unwrap().push((pos, requested))).run(|mut reader| {
+            let mut buf = [0u8; 3];
+            let mut pos = 0;
+            while pos < 3 {
+                pos += reader.read(&mut buf[pos..]).unwrap();
+            }
+            assert_eq!(buf, [1, 2, 3]);
+        });
+        let splits = splits.lock().unwrap();
+        assert!(splits.contains(&(1, 3)));
+        assert!(splits.contains(&(2, 2)));
+        assert!(splits.iter().all(|&(pos, requested)| pos > 0 && pos < 3 && requested > 0));
+    }
+
+    #[test]
+    fn trace() {
+        use std::sync::{Arc, Mutex};
+        use crate::trace::IoEvent;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        ReadTest::new(&[1, 0, 2, 0]).trace(move |event| recorded.lock().unwrap().push(event)).run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            reader.seek(SeekFrom::Current(-1)).unwrap();
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, [0, 2, 0]);
+        });
+        let expected = [
+            IoEvent::Run { split: None, },
+            IoEvent::ReadExact { pos: 0, len: 2, },
+            IoEvent::Seek { pos: 1, },
+            IoEvent::ReadToEnd { pos: 1, returned: 3, },
+        ];
+        assert_eq!(*events.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn trace_search() {
+        use std::sync::{Arc, Mutex};
+        use crate::trace::IoEvent;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        let _ = ReadTest::new(&[1, 0]).trace(move |event| recorded.lock().unwrap().push(event)).try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        let events = events.lock().unwrap();
+        assert_eq!(events[..2], [IoEvent::Run { split: None, }, IoEvent::Read { pos: 0, requested: 2, returned: 1, }]);
+        let split = events.iter().position(|event| *event == IoEvent::Run { split: Some(1), }).unwrap();
+        assert_eq!(events[split + 1], IoEvent::Read { pos: 0, requested: 2, returned: 1, });
+    }
+
+    #[test]
+    fn read_to_end_breaking() {
+        ReadTest::new(&[1, 2, 3, 4]).run(|mut reader| {
+            let mut first = [0u8; 1];
+            reader.read_exact(&mut first).unwrap();
+            let mut rest = vec![0];
+            assert_eq!(reader.read_to_end(&mut rest).unwrap(), 3);
+            assert_eq!(rest, [0, 2, 3, 4]);
+            assert_eq!(reader.read_to_end(&mut rest).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn read_to_end_searching() {
+        ReadTest::new(&[1, 2, 3, 4]).skip_breaking().run(|mut reader| {
+            let mut first = [0u8; 1];
+            reader.read_exact(&mut first).unwrap();
+            let mut rest = vec![0];
+            assert_eq!(reader.read_to_end(&mut rest).unwrap(), 3);
+            assert_eq!(rest, [0, 2, 3, 4]);
+            assert_eq!(reader.read_to_end(&mut rest).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn tracked_peeking() {
+        ReadTest::new(b"hello world").run(|mut reader| {
+            let mut storage = [0u8; 8];
+            let mut buf = super::TrackedBuf::new(&mut storage);
+            let mut text = Vec::new();
+            while reader.read_tracked(&mut buf).unwrap() > 0 {
+                // peeks at the whole buffer but uses only the returned data
+                assert!(std::str::from_utf8(buf.buf()).is_ok());
+                let data = buf.filled().to_vec();
+                text.extend_from_slice(&data);
+                buf.consume(data.len());
+            }
+            assert_eq!(text, b"hello world");
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 1: consumer used 2 bytes of the buffer but the last read returned only 1 unconsumed bytes"]
+    fn tracked_overconsumed() {
+        ReadTest::new(&[1, 0]).run(|mut reader| {
+            let mut storage = [0u8; 2];
+            let mut buf = super::TrackedBuf::new(&mut storage);
+            reader.read_tracked(&mut buf).unwrap();
+            // buggy: assumes the read filled the buffer
+            buf.consume(2);
+            assert_eq!(u16::from_le_bytes(storage), 1);
+        });
+    }
+
+    #[test]
+    fn tracked_not_poisoned() {
+        let mut reader = super::TestReader::with_splits(&[1, 2, 3], &[1]);
+        let mut storage = [0u8; 3];
+        let mut buf = super::TrackedBuf::new(&mut storage);
+        assert_eq!(reader.read_tracked(&mut buf).unwrap(), 1);
+        assert_eq!(buf.buf(), [1, 0, 0]);
+        buf.consume(1);
+        assert!(buf.filled().is_empty());
+        assert_eq!(reader.read_tracked(&mut buf).unwrap(), 2);
+        assert_eq!(buf.filled(), [2, 3]);
+    }
+
+    #[test]
+    fn read_to_end_not_split_in_search() {
+        use std::sync::{Arc, Mutex};
+
+        let splits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&splits);
+        ReadTest::new(&[1, 2, 3, 4]).skip_breaking().on_split(move |pos, requested| recorded.lock().unwrap().push((pos, requested))).run(|mut reader| {
+            let mut data = Vec::new();
+            assert_eq!(reader.read_to_end(&mut data).unwrap(), 4);
+            assert_eq!(data, [1, 2, 3, 4]);
+        });
+        assert!(splits.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_to_end_sabotaged_in_search() {
+        use std::sync::{Arc, Mutex};
+
+        let splits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&splits);
+        ReadTest::new(&[1, 2, 3, 4]).skip_breaking().sabotage_all().on_split(move |pos, requested| recorded.lock().unwrap().push((pos, requested))).run(|mut reader| {
+            let mut data = Vec::new();
+            assert_eq!(reader.read_to_end(&mut data).unwrap(), 4);
+            assert_eq!(data, [1, 2, 3, 4]);
+        });
+        let splits = splits.lock().unwrap();
+        assert_eq!(splits.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn read_to_end_failure_is_not_split() {
+        let err = ReadTest::new(&[1, 0]).run_no_panic(|mut reader| {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).unwrap();
+            // buggy: wrong endianness
+            assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), 1, "parse failed");
+        }).unwrap_err();
+        assert!(err.failure_info.is_none());
+        assert_eq!(err.last_method, Some(Method::ReadToEnd));
+    }
+
+    #[test]
+    #[should_panic = "failure is not split-related (consumer used read_to_end): parse failed"]
+    fn read_to_end_failure_message() {
+        ReadTest::new(&[1, 0]).run(|mut reader| {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).unwrap();
+            assert!(u16::from_be_bytes([buf[0], buf[1]]) == 1, "parse failed");
+        });
+    }
+
+    #[test]
+    fn take_adaptor() {
+        ReadTest::new(&[1, 0, 2, 0]).run(|mut reader| {
+            let mut buf = [0u8; 2];
+            (&mut reader).take(2).read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [1, 0]);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [2, 0]);
+        });
+
+        let err = ReadTest::new(&[1, 0, 2, 0]).run_no_panic(|reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes the buffer was filled
+            reader.take(2).read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+        assert_eq!(err.last_method, Some(Method::Read));
+    }
+
+    #[test]
+    fn chain_adaptor() {
+        ReadTest::new(&[1, 0]).run(|reader| {
+            let mut buf = [0u8; 4];
+            reader.chain(&[2, 0][..]).read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [1, 0, 2, 0]);
+        });
+
+        let err = ReadTest::new(&[1, 0]).run_no_panic(|reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes the buffer was filled
+            reader.chain(&[2, 0][..]).read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+        assert_eq!(err.last_method, Some(Method::Read));
+    }
+
+    #[test]
+    fn empty_reads_allowed() {
+        ReadTest::new(&[1, 0]).run(|mut reader| {
+            assert_eq!(reader.read(&mut []).unwrap(), 0);
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(reader.read(&mut []).unwrap(), 0);
+            assert_eq!(buf, [1, 0]);
+        });
+    }
+
+    #[test]
+    fn with_splits() {
+        let mut reader = super::TestReader::with_splits(&[1, 2, 3, 4, 5], &[1, 3]);
+        let mut buf = [0u8; 5];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_ne!(buf[1], 2);
+        assert_eq!(reader.read(&mut buf[1..]).unwrap(), 2);
+        assert_eq!(reader.read(&mut buf[3..]).unwrap(), 2);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+
+        let mut reader = super::TestReader::with_splits(&[1, 2, 3, 4, 5], &[2]);
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic = "split positions [3, 1] are not strictly increasing"]
+    fn with_splits_unsorted() {
+        super::TestReader::with_splits(&[1, 2, 3, 4, 5], &[3, 1]);
+    }
+
+    #[test]
+    #[should_panic = "split positions [5] must be between 1 and 4"]
+    fn with_splits_out_of_range() {
+        super::TestReader::with_splits(&[1, 2, 3, 4, 5], &[5]);
+    }
+
+    #[test]
+    fn detect_shrinking_reads_correct_loop() {
+        ReadTest::new(&[1, 2, 3, 4, 5, 6, 7, 8]).detect_shrinking_reads().run(|mut reader| {
+            let mut buf = [0u8; 8];
+            let mut pos = 0;
+            while pos < buf.len() {
+                pos += reader.read(&mut buf[pos..]).unwrap();
+            }
+            assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer requested progressively shrinking buffers, the remaining length is likely computed wrong"]
+    fn detect_shrinking_reads() {
+        ReadTest::new(&[1, 2, 3, 4, 5, 6, 7, 8]).detect_shrinking_reads().run(|mut reader| {
+            let mut data = Vec::new();
+            let mut remaining = 8usize;
+            while remaining > 0 {
+                let mut chunk = vec![0u8; remaining];
+                let read = reader.read(&mut chunk).unwrap();
+                data.extend_from_slice(&chunk[..read]);
+                // buggy: off by one
+                remaining = remaining.saturating_sub(read + 1);
+            }
+            assert_eq!(data, [1, 2, 3, 4, 5, 6, 7, 8]);
+        });
+    }
+
+    #[test]
+    fn empty_read_exact() {
+        for sabotage in &[false, true] {
+            let test = ReadTest::new(&[1, 0, 2]);
+            let test = if *sabotage { test.sabotage_all() } else { test };
+            test.run(|mut reader| {
+                reader.read_exact(&mut []).unwrap();
+                let mut buf = [0u8; 3];
+                reader.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, [1, 0, 2]);
+                // at EOF too
+                reader.read_exact(&mut []).unwrap();
+            });
+        }
+    }
+
+    #[test]
+    #[should_panic = "consumer requested a zero-length read_exact"]
+    fn forbid_empty_read_exact() {
+        ReadTest::new(&[1, 0, 2]).forbid_empty_read_exact().run(|mut reader| {
+            let mut buf = [0u8; 3];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: doesn't detect EOF
+            assert!(reader.read_exact(&mut []).is_err(), "expected end of data");
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer requested a zero-length read"]
+    fn forbid_empty_reads() {
+        ReadTest::new(&[1, 0, 2]).forbid_empty_reads().run(|mut reader| {
+            let mut buf = [0u8; 3];
+            let mut len = 0;
+            // buggy: keeps reading after the buffer is full
+            while reader.read(&mut buf[len..]).unwrap() != 0 {
+                len += 1;
+            }
+        });
+    }
+
+    #[test]
+    fn reports_last_method() {
+        let err = ReadTest::new(&[1, 0, 1, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert_eq!(err.last_method, Some(Method::Read));
+
+        let err = ReadTest::new(&[1, 0, 1, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert_eq!(err.last_method, Some(Method::ReadExact));
+    }
+
+    fn decode_record(reader: &mut super::TestReader<'_>) -> std::io::Result<u16> {
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    #[test]
+    fn iter() {
+        test_read_iter(&[1, 0, 2, 0, 3, 0], &[1, 2, 3], decode_record);
+    }
+
+    #[test]
+    #[should_panic = "value #1 mismatched at split pos 4: decoded"]
+    fn iter_reports_index() {
+        // length-prefixed records
+        test_read_iter(&[1, 7, 2, 1, 0], &[7u16, 1], |reader| {
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len)?;
+            let mut buf = [0u8; 2];
+            // buggy: only single-byte records are guaranteed to be read whole
+            reader.read(&mut buf[..usize::from(len[0])])?;
+            Ok(u16::from_le_bytes(buf))
+        });
+    }
+
+    #[test]
+    fn schedule() {
+        test_read_schedule(&[1, 2, 3, 4, 5, 6, 7, 8], &[1, 3, 1, 2], |mut reader| {
+            let mut buf = [0u8; 8];
+            let lens = (0..5).map(|_| reader.read(&mut buf).unwrap()).collect::<Vec<_>>();
+            assert_eq!(lens, [1, 3, 1, 2, 1]);
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn schedule_clamped_to_buffer() {
+        test_read_schedule(&[1, 2, 3, 4], &[3], |mut reader| {
+            let mut buf = [0u8; 2];
+            assert_eq!(reader.read(&mut buf).unwrap(), 2);
+            assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed with read schedule [1, 3]: assertion"]
+    fn schedule_reproduces_failure() {
+        test_read_schedule(&[1, 0, 0, 0, 2, 0], &[1, 3], |mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(u32::from_le_bytes(buf), 1);
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+        });
+    }
+
+    #[test]
+    #[should_panic = "chunk sizes must be non-zero"]
+    fn schedule_with_zero() {
+        test_read_schedule(&[1, 2], &[1, 0], |_| ());
+    }
+
+    #[test]
+    #[should_panic = "chunk sizes sum to 3 but the input has only 2 bytes"]
+    fn schedule_too_long() {
+        test_read_schedule(&[1, 2], &[1, 2], |_| ());
+    }
+
+    #[test]
+    #[should_panic = "method transition: read_exact(2) then read(2)"]
+    fn reports_method_transition() {
+        ReadTest::new(&[1, 0, 2, 0]).run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+            // buggy: reuses the header buffer for a short read
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+        });
+    }
+
+    #[test]
+    fn no_transition_for_single_method() {
+        let err = ReadTest::new(&[1, 0, 0, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u32::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert!(err.transition.is_none());
+    }
+
+    #[test]
+    fn reports_later_reproducing_positions() {
+        let err = ReadTest::new(&[1, 0, 2, 0]).find_all_failures().run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert!(u16::from_le_bytes(buf) == 1, "first value");
+            reader.read(&mut buf).unwrap();
+            assert!(u16::from_le_bytes(buf) == 2, "second value");
+        }).unwrap_err();
+        let info = err.failure_info.as_ref().unwrap();
+        assert_eq!(info.pos, 1);
+        // position 3 fails with a different message
+        assert!(info.also_reproduced.is_empty());
+        assert_eq!(info.stats.as_ref().unwrap().to_string(), "2 positions failed with 2 distinct messages");
+
+        let err = ReadTest::new(&[1, 0, 2, 0]).find_all_failures().run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            let first = u16::from_le_bytes(buf);
+            reader.read(&mut buf).unwrap();
+            assert!(first == 1 && u16::from_le_bytes(buf) == 2, "wrong values");
+        }).unwrap_err();
+        let info = err.failure_info.as_ref().unwrap();
+        assert_eq!(info.pos, 1);
+        assert_eq!(info.also_reproduced, [3]);
+    }
+
+    #[test]
+    #[should_panic = "2 positions failed with 2 distinct messages"]
+    fn distinct_messages_in_message() {
+        ReadTest::new(&[1, 0, 2, 0]).find_all_failures().run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert!(u16::from_le_bytes(buf) == 1, "first value");
+            reader.read(&mut buf).unwrap();
+            assert!(u16::from_le_bytes(buf) == 2, "second value");
+        });
+    }
+
+    #[test]
+    #[should_panic = "the same failure also reproduced at later positions: [3]"]
+    fn later_reproducing_positions_in_message() {
+        ReadTest::new(&[1, 0, 2, 0]).find_all_failures().run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            let first = u16::from_le_bytes(buf);
+            reader.read(&mut buf).unwrap();
+            assert!(first == 1 && u16::from_le_bytes(buf) == 2, "wrong values");
+        });
+    }
+
+    #[test]
+    fn reports_last_read() {
+        let err = ReadTest::new(&[1, 0, 0, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u32::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        let last_read = err.failure_info.as_ref().unwrap().last_read.unwrap();
+        assert_eq!((last_read.requested, last_read.returned), (4, 1));
+    }
+
+    #[test]
+    fn take_framing() {
+        fn decode<R: Read>(mut reader: R) {
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len).unwrap();
+            let mut body = Vec::new();
+            (&mut reader).take(len[0].into()).read_to_end(&mut body).unwrap();
+            assert_eq!(body, [1, 2]);
+            let mut trailer = [0u8; 1];
+            reader.read_exact(&mut trailer).unwrap();
+            assert_eq!(trailer, [3]);
+        }
+
+        ReadTest::new(&[2, 1, 2, 3]).run(|reader| decode(reader));
+        // the limit ends exactly at the split
+        decode(super::TestReader::with_splits(&[2, 1, 2, 3], &[3]));
+        decode(super::TestReader::with_splits(&[2, 1, 2, 3], &[1, 3]));
+    }
+
+    #[test]
+    fn take_larger_than_input() {
+        ReadTest::new(&[1, 2, 3]).run(|reader| {
+            let mut all = Vec::new();
+            reader.take(10).read_to_end(&mut all).unwrap();
+            assert_eq!(all, [1, 2, 3]);
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 2"]
+    fn take_wrong_limit() {
+        ReadTest::new(&[2, 1, 2, 3]).run(|mut reader| {
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len).unwrap();
+            // buggy: assumes `read` of the limited reader fills the buffer
+            let mut body = [0u8; 2];
+            (&mut reader).take(len[0].into()).read(&mut body).unwrap();
+            assert_eq!(body, [1, 2]);
+        });
+    }
+
+    #[test]
+    #[cfg(all(feature = "backtrace", not(miri)))]
+    fn take_culprit() {
+        let err = ReadTest::new(&[2, 1, 2]).run_no_panic(|mut reader| {
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len).unwrap();
+            // buggy: assumes `read` of the limited reader fills the buffer
+            let mut body = [0u8; 2];
+            reader.take(len[0].into()).read(&mut body).unwrap();
+            assert_eq!(body, [1, 2]);
+        }).unwrap_err();
+        // the split inside the limited reader is attributed to the consumer's `read`, symbol names
+        // differ between toolchains so only the capture is checked
+        let info = err.failure_info.unwrap();
+        assert_eq!(info.pos, 2);
+        assert!(info.backtrace.is_some());
+        assert_eq!(err.last_method, Some(Method::Read));
+    }
+
+    #[test]
+    fn all_failure_backtraces() {
+        let failure = ReadTest::new(&[1, 0, 2, 0]).find_all_failures().try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+        }).unwrap_err();
+        let backtraces = failure.all_failure_backtraces();
+        let positions = backtraces.iter().map(|(pos, _)| *pos).collect::<Vec<_>>();
+        assert_eq!(positions, failure.all_failures());
+        #[cfg(all(feature = "backtrace", not(miri)))]
+        {
+            // symbol names differ between toolchains so only the presence of the backtraces is
+            // checked, each position captured its own
+            let info = failure.0.failure_info.as_ref().unwrap();
+            assert!(info.backtrace.is_some());
+            assert_eq!(info.later_backtraces.len(), 1);
+            assert!(info.later_backtraces.iter().all(Option::is_some));
+            // each position implicates a different `read` call
+            assert_ne!(backtraces[0].1, backtraces[1].1);
+        }
+    }
+
+    #[test]
+    #[should_panic = "last read: requested 4, returned 1"]
+    fn last_read_in_message() {
+        ReadTest::new(&[1, 0, 0, 0]).run(|mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u32::from_le_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    #[should_panic = "hint: the failing `read` requested 4 bytes but readers may return fewer; consider `read_exact`"]
+    fn read_exact_hint() {
+        ReadTest::new(&[1, 0, 0, 0]).run(|mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u32::from_le_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    fn read_exact_hint_only_for_short_read() {
+        let err = ReadTest::new(&[1, 0, 2, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: forgets the last byte, unrelated to the buffer size
+            let mut byte = [0u8; 1];
+            reader.read(&mut byte).unwrap();
+            assert_eq!(byte, [2]);
+            assert_eq!(reader.read(&mut byte).unwrap(), 0);
+        }).unwrap_err();
+        assert!(!err.to_string().contains("hint:"));
+    }
+
+    #[test]
+    #[allow(clippy::unbuffered_bytes)] // testing `bytes()` is the point
+    fn bytes() {
+        ReadTest::new(&[1, 2, 3]).run(|reader| {
+            let bytes = reader.bytes().collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(bytes, [1, 2, 3]);
+        });
+    }
+
+    #[test]
+    #[allow(clippy::unbuffered_bytes)] // testing `bytes()` is the point
+    fn bytes_mishandled_eof() {
+        let err = ReadTest::new(&[1, 2, 3]).run_no_panic(|reader| {
+            let mut bytes = reader.bytes();
+            let mut sum = 0u8;
+            // buggy: treats EOF as a zero byte and expects four bytes
+            for _ in 0..4 {
+                sum += bytes.next().unwrap_or(Ok(0)).unwrap();
+            }
+            assert_eq!(sum, 7);
+        }).unwrap_err();
+        let failure_info = err.failure_info.unwrap();
+        assert_eq!(failure_info.pos, 1);
+        assert!(failure_info.backtrace.is_none());
+    }
+
+    #[test]
+    fn shrink_input() {
+        let err = ReadTest::new(&[1, 0, 2, 0, 3, 0]).shrink_input().try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: assumes `read` fills the buffer
+            while reader.read(&mut buf).unwrap() > 0 {
+                assert!(u16::from_le_bytes(buf) < 256);
+            }
+        }).unwrap_err();
+        assert_eq!(err.shrunk_input(), Some(&[1, 0, 2, 0][..]));
+        assert_eq!(err.position(), Some(3));
+    }
+
+    #[test]
+    fn search_from() {
+        let err = ReadTest::new(&[1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0]).search_from(8).find_all_failures().try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes `read` fills the buffer
+            while reader.read(&mut buf).unwrap() > 0 {
+                assert!(u16::from_le_bytes(buf) < 256);
+            }
+        }).unwrap_err();
+        assert_eq!(err.position(), Some(7));
+        // the remaining positions are tried in the search order, alternating around the start
+        assert_eq!(err.all_failures(), [7, 9, 5, 11, 3, 1]);
+    }
+
+    #[test]
+    fn search_reverse() {
+        let err = ReadTest::new(&[1, 0, 2, 0, 3, 0, 4, 0]).search_reverse().find_all_failures().try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes `read` fills the buffer
+            while reader.read(&mut buf).unwrap() > 0 {
+                assert!(u16::from_le_bytes(buf) < 256);
+            }
+        }).unwrap_err();
+        assert_eq!(err.position(), Some(7));
+        // the remaining positions are tried in the search order
+        assert_eq!(err.all_failures(), [7, 5, 3, 1]);
+    }
+
+    #[test]
+    fn search_from_falls_back() {
+        let err = ReadTest::new(&[1, 0, 2, 0, 3, 0, 4, 0]).search_from(6).try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes `read` fills the buffer
+            reader.read(&mut buf).unwrap();
+            assert_eq!(buf, [1, 0]);
+            let mut rest = [0u8; 6];
+            reader.read_exact(&mut rest).unwrap();
+        }).unwrap_err();
+        assert_eq!(err.position(), Some(1));
+    }
+
+    #[test]
+    #[should_panic = "the search must start at a split position between 1 and 3, 4 given"]
+    fn search_from_invalid() {
+        let _ = ReadTest::new(&[1, 0, 2, 0]).search_from(4);
+    }
+
+    #[test]
+    #[should_panic = "failed but no split position reproduced the failure so it could not be localized, the closure may be non-deterministic"]
+    fn nondeterministic_not_localized() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        ReadTest::new(&[1, 0]).run(|mut reader| {
+            let call = CALLS.fetch_add(1, Ordering::Relaxed);
+            let mut buf = [0u8; 2];
+            // buggy: assumes `read` fills the buffer but checks it only in every other call
+            reader.read(&mut buf).unwrap();
+            if call % 2 == 0 {
+                assert_eq!(buf, [1, 0]);
+            }
+        });
+    }
+
+    #[test]
+    fn retry_positions() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let err = ReadTest::new(&[1, 0]).retry_positions(2).try_run(|mut reader| {
+            let call = CALLS.fetch_add(1, Ordering::Relaxed);
+            let mut buf = [0u8; 2];
+            // buggy: assumes `read` fills the buffer but checks it only in every other call
+            reader.read(&mut buf).unwrap();
+            if call % 2 == 0 {
+                assert_eq!(buf, [1, 0]);
+            }
+        }).unwrap_err();
+        assert_eq!(err.position(), Some(1));
+    }
+
+    #[test]
+    #[allow(clippy::unused_io_amount)]
+    fn sanity() {
+        let buggy = |mut reader: super::TestReader<'_>| {
+            let mut buf = [0u8; 2];
+            // buggy: assumes `read` fills the buffer
+            reader.read(&mut buf).unwrap();
+            assert_eq!(buf, [1, 0]);
+        };
+        sanity_read(&[1, 0], buggy);
+        assert!(try_test_read(&[1, 0], buggy).is_err());
+    }
+
+    #[test]
+    #[should_panic = "failed even with a reader returning whole reads, the bug is unrelated to splitting: assertion"]
+    fn sanity_failure() {
+        sanity_read(&[1, 0], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            // buggy: wrong endianness
+            assert_eq!(u16::from_be_bytes(buf), 1);
+        });
+    }
+
+    #[test]
+    fn typed_payload() {
+        #[derive(Debug, PartialEq)]
+        struct Mismatch(u16);
+
+        let failure = ReadTest::new(&[1, 0]).try_run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            if buf != [1, 0] {
+                std::panic::resume_unwind(Box::new(Mismatch(u16::from_le_bytes(buf))));
+            }
+        }).unwrap_err();
+        assert_eq!(failure.message(), None);
+        assert_eq!(failure.payload().downcast_ref(), Some(&Mismatch(0xff01)));
+        assert!(failure.into_payload().is::<Mismatch>());
+    }
+
+    #[test]
+    fn segments() {
+        test_read_segments(&[&[2, 0], &[1, 2], &[3]], |mut reader| {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len).unwrap();
+            let mut body = vec![0u8; u16::from_le_bytes(len).into()];
+            reader.read_exact(&mut body).unwrap();
+            assert_eq!(body, [1, 2]);
+        });
+    }
+
+    #[test]
+    #[should_panic = "bug A localized at position 2"]
+    fn segments_boundary_first() {
+        // buggy: expects the header and the body to arrive at once, fails at every position
+        test_read_segments(&[&[2, 0], &[1, 2]], |mut reader| {
+            let mut buf = [0u8; 4];
+            reader.read(&mut buf).unwrap();
+            assert_eq!(buf, [2, 0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn segment_boundary_reported() {
+        let err = ReadTest::new(&[2, 0, 1, 2, 3]).segment_boundaries(&[2, 4]).try_run(|mut reader| {
+            let mut header = [0u8; 2];
+            reader.read_exact(&mut header).unwrap();
+            // buggy: expects the body and the trailer to arrive at once
+            let mut rest = [0u8; 3];
+            reader.read(&mut rest).unwrap();
+            assert_eq!(rest, [1, 2, 3]);
+        }).unwrap_err();
+        assert_eq!(err.position(), Some(4));
+        assert!(err.to_string().contains("the position is the boundary between segments #1 and #2"));
+    }
+
+    #[test]
+    #[should_panic = "segment #1 is empty"]
+    fn empty_segment() {
+        test_read_segments(&[&[1], &[], &[2]], |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+        });
+    }
+
+    #[test]
+    fn sabotage_all() {
+        ReadTest::new(&[1, 0, 2, 0, 3]).sabotage_all().run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [1, 0]);
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, [2, 0, 3]);
+            assert_eq!(reader.read_exact(&mut buf).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+        });
+    }
+
+    #[test]
+    #[should_panic = "the test closure never read from the provided reader"]
+    fn unused_reader() {
+        let input = [1, 0];
+        ReadTest::new(&input).run(|_reader| {
+            // buggy: decodes the captured input instead of reading
+            assert_eq!(u16::from_le_bytes(input), 1);
+        });
+    }
+
+    #[test]
+    fn unused_reader_try_run() {
+        let failure = ReadTest::new(&[1, 0]).try_run(|_| ()).unwrap_err();
+        assert_eq!(failure.position(), None);
+        assert_eq!(failure.message(), Some("the test closure never read from the provided reader - the test is not exercising anything"));
+        assert!(failure.to_string().contains("failed: the test closure never read from the provided reader"), "{}", failure);
+    }
+
+    #[test]
+    fn allow_unused_reader() {
+        ReadTest::new(&[1, 0]).allow_unused_reader().run(|_| ());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    #[should_panic = "failed at position 31: short read"]
+    fn parallel_finds_smallest_position() {
+        let input = [42u8; 64];
+        ReadTest::new(&input).run_parallel(|mut reader| {
+            let mut header = [0u8; 30];
+            reader.read_exact(&mut header).unwrap();
+            let mut body = [0u8; 34];
+            // buggy: fails at every position within the body
+            reader.read(&mut body).unwrap();
+            assert!(body.iter().all(|byte| *byte == 42), "short read");
+        });
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_passes() {
+        let input = [42u8; 64];
+        ReadTest::new(&input).run_parallel(|mut reader| {
+            let mut buf = [0u8; 64];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, input);
+        });
+    }
+
+    #[test]
+    fn seek_past_cached_short_read() {
+        let err = ReadTest::new(&[1, 0, 2, 0]).run_no_panic(|mut reader| {
+            let mut header = [0u8; 2];
+            // buggy: caches the header assuming the read filled it
+            reader.read(&mut header).unwrap();
+            reader.seek(SeekFrom::Start(2)).unwrap();
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 2);
+            assert_eq!(u16::from_le_bytes(header), 1);
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+    }
+
+    #[test]
+    fn reread_after_rewind_is_split_again() {
+        let err = ReadTest::new(&[1, 0]).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+            reader.seek(SeekFrom::Start(0)).unwrap();
+            // buggy: the re-read is not handled correctly
+            reader.read(&mut buf).unwrap();
+            assert_eq!(u16::from_le_bytes(buf), 1);
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+    }
+
+    #[test]
+    fn seek_positions() {
+        ReadTest::new(&[1, 2, 3, 4]).run(|mut reader| {
+            assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 3);
+            let mut buf = [0u8; 1];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [4]);
+            assert_eq!(reader.seek(SeekFrom::Current(-3)).unwrap(), 1);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [2]);
+            assert!(reader.seek(SeekFrom::Current(-3)).is_err());
+            assert_eq!(reader.seek(SeekFrom::Start(10)).unwrap(), 10);
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+            // doesn't fit `i64`
+            assert_eq!(reader.seek(SeekFrom::Start(u64::max_value())).unwrap(), u64::max_value());
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+            assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 3);
+        });
+    }
+
+    #[test]
+    fn generated() {
+        test_read_gen(1000, |i| i as u8, |mut reader| {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            assert!(data.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+        });
+    }
+
+    #[test]
+    fn generated_partial_read() {
+        let err = ReadTest::generated(1000, &|i| i as u8).run_no_panic(|mut reader| {
+            let mut buf = [0u8; 300];
+            reader.read_exact(&mut buf[..256]).unwrap();
+            // buggy: assumes the buffer was filled
+            reader.read(&mut buf[256..]).unwrap();
+            assert!(buf.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(257));
+    }
+
+    #[test]
+    fn generated_debug() {
+        test_read_gen(16, |i| i as u8, |mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read_exact(&mut buf).unwrap();
+            let debug = format!("{:?}", reader);
+            assert!(debug.contains("consumed: 2, remaining: 14, upcoming: [2, 3, 4, 5, 6, 7, 8, 9, ..]"), "{}", debug);
+        });
+    }
+
+    #[cfg(feature = "read_buf")]
+    #[test]
+    fn read_buf() {
+        use std::io::BorrowedBuf;
+        use std::mem::MaybeUninit;
+
+        let err = ReadTest::new(&[1, 0]).run_no_panic(|mut reader| {
+            let mut storage = [MaybeUninit::uninit(); 2];
+            let mut buf = BorrowedBuf::from(&mut storage[..]);
+            // buggy: assumes the buffer was filled
+            reader.read_buf(buf.unfilled()).unwrap();
+            assert_eq!(buf.len(), 2);
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+        assert_eq!(err.last_method, Some(Method::ReadBuf));
     }
 }
@@ -0,0 +1,94 @@
+//! Customization of how test failures are presented.
+
+use crate::read::ReadFailure;
+use crate::write::WriteFailure;
+
+/// Presents failures of read and write tests.
+///
+/// By default failed tests panic with a message describing the failure. Implementing this trait
+/// allows routing the failures elsewhere, e.g. into assertion macros of a test framework or a
+/// custom harness. The provided methods panic the same way as the default reporter so only the
+/// relevant ones need to be implemented.
+///
+/// If a method returns without panicking the failure is considered handled and the test returns
+/// normally.
+pub trait Reporter {
+    /// Called when a read test fails.
+    fn on_read_failure(&self, failure: ReadFailure) {
+        failure.panic()
+    }
+
+    /// Called when a write test fails.
+    fn on_write_failure(&self, failure: WriteFailure) {
+        failure.panic()
+    }
+}
+
+/// Reporter panicking with a message describing the failure, used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PanicReporter;
+
+impl Reporter for PanicReporter {}
+
+#[cfg(test)]
+// the tests intentionally contain buggy IO code
+#[allow(clippy::unused_io_amount)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use crate::read::{ReadFailure, ReadTest};
+    use crate::write::{WriteFailure, WriteTest};
+    use super::Reporter;
+
+    #[derive(Default)]
+    struct Collect(RefCell<Vec<(Option<usize>, String)>>);
+
+    impl Reporter for Collect {
+        fn on_read_failure(&self, failure: ReadFailure) {
+            self.0.borrow_mut().push((failure.position(), failure.message().unwrap_or_default().to_owned()));
+        }
+
+        fn on_write_failure(&self, failure: WriteFailure) {
+            self.0.borrow_mut().push((failure.position(), failure.message().unwrap_or_default().to_owned()));
+        }
+    }
+
+    #[test]
+    fn custom_reporter() {
+        let reporter = Collect::default();
+        ReadTest::new(&[1, 0]).report_with(&reporter).run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert!(u16::from_le_bytes(buf) == 1, "wrong value");
+        });
+        WriteTest::new(&[1, 0]).report_with(&reporter).run(|mut writer| {
+            writer.write(&[1, 0]).unwrap();
+        });
+        let reports = reporter.0.into_inner();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0], (Some(1), "wrong value".to_owned()));
+        assert_eq!(reports[1].0, Some(1));
+    }
+
+    #[test]
+    fn passing_test_not_reported() {
+        let reporter = Collect::default();
+        ReadTest::new(&[1, 0]).report_with(&reporter).run(|mut reader| {
+            reader.read_exact(&mut [0u8; 2]).unwrap();
+        });
+        assert!(reporter.0.into_inner().is_empty());
+    }
+
+    #[test]
+    #[should_panic = "failed at position 1: wrong value"]
+    fn default_reporter_panics() {
+        struct Provided;
+        impl Reporter for Provided {}
+
+        ReadTest::new(&[1, 0]).report_with(&Provided).run(|mut reader| {
+            let mut buf = [0u8; 2];
+            reader.read(&mut buf).unwrap();
+            assert!(u16::from_le_bytes(buf) == 1, "wrong value");
+        });
+    }
+}
@@ -0,0 +1,78 @@
+//! Decisions about splitting data, independent of `std`.
+//!
+//! Testing readers and writers has two layers: this one decides how much data is handed over in
+//! each call and how the rest of the buffer is poisoned, the other one implements the IO traits,
+//! catches panics and searches for the failing split. Only the latter needs `std`
+//! (`catch_unwind`, `std::io`), so this module uses `core` only. The crate as a whole still
+//! requires `std` - keeping the decisions separate is just a preparation for a `no_std` breaking
+//! checker, which doesn't exist yet.
+
+/// Returns the number of bytes a breaking reader or writer hands over in a single call.
+///
+/// The first `greedy` bytes are handed over at once (as much as `requested` allows), then a
+/// single byte per call.
+pub(crate) fn breaking_len(greedy: usize, requested: usize) -> usize {
+    if greedy > 0 {
+        greedy.min(requested).max(1)
+    } else {
+        1
+    }
+}
+
+/// Returns the shortened length if a call at `pos` requesting `requested` bytes crosses `split`.
+pub(crate) fn split_len(pos: usize, split: usize, requested: usize) -> Option<usize> {
+    if pos < split && split < pos + requested {
+        Some(split - pos)
+    } else {
+        None
+    }
+}
+
+/// Makes the byte after the first `len` bytes of `buf` differ from the `next` byte of input.
+///
+/// Consumers wrongly assuming the whole buffer was filled then see invalid data even if they
+/// zeroed the buffer and the input contains zeros.
+pub(crate) fn poison(buf: &mut [u8], len: usize, next: Option<&u8>) {
+    poison_with(buf, len, next, |next| !next)
+}
+
+/// Sets the byte after the first `len` bytes of `buf` to the value `poison` computes from the
+/// `next` byte of input.
+pub(crate) fn poison_with<F: FnOnce(u8) -> u8>(buf: &mut [u8], len: usize, next: Option<&u8>, poison: F) {
+    if let (Some(byte), Some(next)) = (buf.get_mut(len), next) {
+        *byte = poison(*next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{breaking_len, poison, poison_with, split_len};
+
+    #[test]
+    fn breaking() {
+        assert_eq!(breaking_len(0, 4), 1);
+        assert_eq!(breaking_len(2, 4), 2);
+        assert_eq!(breaking_len(8, 4), 4);
+        assert_eq!(breaking_len(8, 0), 1);
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_len(0, 2, 4), Some(2));
+        assert_eq!(split_len(0, 4, 4), None);
+        assert_eq!(split_len(2, 2, 4), None);
+        assert_eq!(split_len(3, 2, 4), None);
+    }
+
+    #[test]
+    fn poisoning() {
+        let mut buf = [0; 3];
+        poison(&mut buf, 1, Some(&0));
+        assert_eq!(buf, [0, 0xff, 0]);
+        poison(&mut buf, 3, Some(&0));
+        poison(&mut buf, 0, None);
+        assert_eq!(buf, [0, 0xff, 0]);
+        poison_with(&mut buf, 2, Some(&1), |next| next + 1);
+        assert_eq!(buf, [0, 0xff, 2]);
+    }
+}
@@ -0,0 +1,59 @@
+//! Observing the IO calls made by the tested code.
+
+use std::panic::RefUnwindSafe;
+
+/// IO call made by the tested closure, passed to the tracer.
+///
+/// Tracers are installed using [`ReadTest::trace`](crate::read::ReadTest::trace) and
+/// [`WriteTest::trace`](crate::write::WriteTest::trace). The closure is called multiple times
+/// during a test, each time with a fresh reader or writer, which is marked by [`IoEvent::Run`].
+/// Positions are offsets in the input or expected data at which the call started. Calls are only
+/// traced if they succeeded, so e.g. an injected error is not traced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IoEvent {
+    /// The closure was called with a new reader or writer.
+    Run {
+        /// The position at which the reader or writer splits the data if it splits it at a single
+        /// position, `None` if it splits it everywhere or not at all.
+        split: Option<usize>,
+    },
+    /// `read` returned data. Reads using `read_buf` are traced as this as well.
+    Read {
+        pos: usize,
+        requested: usize,
+        returned: usize,
+    },
+    /// `read_exact` filled the buffer.
+    ReadExact {
+        pos: usize,
+        len: usize,
+    },
+    /// `read_to_end` read the rest of the input.
+    ReadToEnd {
+        pos: usize,
+        returned: usize,
+    },
+    /// The reader was seeked to `pos`, positions past the end of the input are clamped.
+    Seek {
+        pos: usize,
+    },
+    /// `write` accepted data.
+    Write {
+        pos: usize,
+        offered: usize,
+        accepted: usize,
+    },
+    /// `write_all` wrote all the data.
+    WriteAll {
+        pos: usize,
+        len: usize,
+    },
+    /// `flush` was called.
+    Flush {
+        pos: usize,
+    },
+}
+
+/// Observes the IO calls made by the tested closure.
+pub(crate) type TraceFn = dyn Fn(IoEvent) + Send + Sync + RefUnwindSafe;
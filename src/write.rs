@@ -1,9 +1,19 @@
 //! Contains items related to testing of `Write` usage.
 
-use std::io::{self, Write};
-use std::panic::{UnwindSafe, RefUnwindSafe};
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe, UnwindSafe, RefUnwindSafe};
+use std::sync::Arc;
 
-use crate::backtrace_impl::{Backtrace, BacktraceStorageMut, DisplayBacktrace};
+use crate::backtrace_impl::{self, BacktraceStorageMut, Operation, TestLocation};
+use crate::error::{self, Error, NormalizeFn, UnlocalizedFailure};
+use crate::hex::{HexDiff, Truncated, TruncatedTail, TRUNCATED_LEN};
+use crate::report::{PanicReporter, Reporter};
+use crate::split;
+use crate::trace::{IoEvent, TraceFn};
 
 /// Writer that splits writes the to test `Writer` consumers.
 ///
@@ -12,235 +22,2111 @@ use crate::backtrace_impl::{Backtrace, BacktraceStorageMut, DisplayBacktrace};
 /// on-the-fly and panic directly, there is no guarantee that it will always perform checking this
 /// way.
 ///
+/// Currently the writer accepts one byte per `write` call and, if the closure panics, it splits
+/// the output in two to find the position where the problem occurs.
+///
+/// Wrapping the writer in `BufWriter` is supported: the bytes reaching the writer are checked
+/// when the buffer is flushed and `BufWriter` handles the partial writes itself. Keep in mind that
+/// writes into `BufWriter` are never partial while they fit into its buffer, so partial-write
+/// bugs of the code writing into it stay hidden. Test such code with the writer directly.
+///
+/// Formatting with `write!` and `writeln!` is checked too: `write_fmt` passes each chunk the
+/// formatter emits to `write_all`, which splits it like any other data. The writer never returns
+/// IO errors so `write_fmt` can not turn a problem into a generic formatting error.
+///
 /// [`test_write`]: super::test_write
 pub struct TestWriter<'a> {
-    expected: &'a [u8],
+    expected: Expected<'a>,
+    // not advanced, used to display the accepted data
+    all_expected: Expected<'a>,
     // must be mut ref so that `test_write` can check length
     stats: &'a mut WriteStats,
+    mode: Mode<'a>,
+    config: &'a Config<'a>,
+}
+
+/// Data the writer expects to be written, possibly generated on the fly.
+#[derive(Clone, Copy)]
+enum Expected<'a> {
+    Slice(&'a [u8]),
+    Generated {
+        // offset of the next expected byte
+        start: usize,
+        end: usize,
+        generator: &'a (dyn Fn(usize) -> u8 + RefUnwindSafe),
+    },
+    Streamed {
+        // offset of the next expected byte
+        start: usize,
+        end: usize,
+        stream: &'a (dyn ReadExpected + RefUnwindSafe),
+    },
+}
+
+impl<'a> Expected<'a> {
+    fn len(&self) -> usize {
+        match *self {
+            Expected::Slice(slice) => slice.len(),
+            Expected::Generated { start, end, .. } => end - start,
+            Expected::Streamed { start, end, .. } => end - start,
+        }
+    }
+
+    /// Returns `len` expected bytes starting at `offset`, generating or reading them if needed.
+    fn get(&self, offset: usize, len: usize) -> Cow<'a, [u8]> {
+        match *self {
+            Expected::Slice(slice) => Cow::Borrowed(&slice[offset..(offset + len)]),
+            Expected::Generated { start, generator, .. } => ((start + offset)..(start + offset + len)).map(generator).collect(),
+            Expected::Streamed { start, end, stream, } => Cow::Owned(stream.get(start + offset, len, end)),
+        }
+    }
+
+    /// Checks whether `data` equals the expected bytes starting at `offset`.
+    fn matches(&self, offset: usize, data: &[u8]) -> bool {
+        if offset + data.len() > self.len() {
+            return false;
+        }
+        match *self {
+            Expected::Slice(slice) => slice[offset..(offset + data.len())] == *data,
+            Expected::Generated { start, generator, .. } => {
+                data.iter().zip((start + offset)..).all(|(byte, i)| generator(i) == *byte)
+            },
+            Expected::Streamed { .. } => self.get(offset, data.len()) == data,
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        match self {
+            Expected::Slice(slice) => *slice = &slice[len..],
+            Expected::Generated { start, .. } => *start += len,
+            Expected::Streamed { start, .. } => *start += len,
+        }
+    }
+}
+
+/// Provides expected bytes read from a stream on demand.
+trait ReadExpected {
+    /// Returns `len` bytes starting at absolute `offset` of the stream containing `total` bytes.
+    fn get(&self, offset: usize, len: usize, total: usize) -> Vec<u8>;
+}
+
+/// Expected bytes read from readers created by `make_reader`.
+///
+/// Only the bytes of the current write are buffered. Every run of the closure writes from the
+/// beginning, so when an earlier offset is requested a new reader is created and read again.
+// It's UnwindSafe because the window is reset whenever it doesn't match the request
+struct ExpectedStream<F, R> {
+    make_reader: F,
+    window: AssertUnwindSafe<RefCell<Window<R>>>,
+}
+
+/// Buffered part of the expected stream.
+struct Window<R> {
+    reader: Option<R>,
+    // offset of the first buffered byte
+    start: usize,
+    buf: Vec<u8>,
+}
+
+impl<F, R> ReadExpected for ExpectedStream<F, R> where F: Fn() -> R, R: Read {
+    fn get(&self, offset: usize, len: usize, total: usize) -> Vec<u8> {
+        let mut window = self.window.borrow_mut();
+        let window = &mut *window;
+        if offset < window.start || window.reader.is_none() {
+            window.reader = Some((self.make_reader)());
+            window.start = 0;
+            window.buf.clear();
+        }
+        // the bytes before `offset` are not needed anymore
+        let skip = (offset - window.start).min(window.buf.len());
+        window.buf.drain(..skip);
+        window.start += skip;
+        let reader = window.reader.as_mut().expect("the reader was created above");
+        let mut chunk = [0u8; 4096];
+        while window.start + window.buf.len() < offset + len {
+            let missing = (offset + len - window.start - window.buf.len()).min(chunk.len());
+            let read = match reader.read(&mut chunk[..missing]) {
+                Ok(0) => panic!("the expected reader ended after {} bytes but {} bytes were expected", window.start + window.buf.len(), total),
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => panic!("reading expected data at offset {} failed: {}", window.start + window.buf.len(), error),
+            };
+            // the buffer is empty until `offset` is reached, the bytes before it are discarded
+            // right away so that at most `len` bytes are buffered
+            let skipped = (offset - window.start).min(read);
+            window.buf.extend_from_slice(&chunk[skipped..read]);
+            window.start += skipped;
+        }
+        window.buf[(offset - window.start)..(offset - window.start + len)].to_vec()
+    }
+}
+
+/// Expected data of `WriteTest`, possibly owning the state of the stream it's read from.
+enum ExpectedSource<'a> {
+    Borrowed(Expected<'a>),
+    Streamed {
+        len: usize,
+        stream: Box<dyn ReadExpected + RefUnwindSafe + 'a>,
+    },
+}
+
+impl ExpectedSource<'_> {
+    fn get(&self) -> Expected<'_> {
+        match self {
+            ExpectedSource::Borrowed(expected) => *expected,
+            ExpectedSource::Streamed { len, stream, } => Expected::Streamed { start: 0, end: *len, stream: &**stream, },
+        }
+    }
+}
+
+/// Controls how much data the writer accepts.
+enum Mode<'a> {
+    /// Accepts a single byte in each `write` call.
+    Breaking,
+    /// Accepts whole writes except for the one crossing `split`, which is shortened.
+    Searching {
+        split: usize,
+        backtrace: BacktraceStorageMut<'a>,
+    },
+    /// Accepts whole writes until `greedy` bytes are written, then a single byte per call.
+    Greedy {
+        greedy: usize,
+    },
+    /// Accepts whole writes.
+    Whole,
 }
 
 /// Write stats used to diagnose issues.
 #[derive(Default)]
 struct WriteStats {
     pos: usize,
-    last_call: Option<Backtrace>,
     last_unwritten: usize,
+    // position at which the last partial write started
+    last_write_pos: usize,
+    // lengths of data passed to `write` and `write_all`
+    write_sizes: Vec<usize>,
+    // data of the last partial write
+    last_partial_data: Vec<u8>,
+    // number of consecutive partial writes that resent the same data
+    repeated_writes: usize,
+    // number of writes rejected because the writer was full
+    full_writes: usize,
+    // positions at which `flush` was called
+    flush_offsets: Vec<usize>,
+    // numbers of calls made by the consumer, `write_all` is not counted as `write`
+    write_calls: usize,
+    write_all_calls: usize,
+    // accepted bytes, only recorded when requested
+    written: Option<Vec<u8>>,
 }
 
 impl WriteStats {
     fn emit_unhandled_partial_write(&self) -> ! {
         // If there was no previous write it couldn't be unhandled
         assert_ne!(self.pos, 0, "internal consistency check failed, this is a bug in the check_io library, not your code");
-        let backtrace = DisplayBacktrace::write(&self.last_call);
-        panic!("the write call at position {} didn't handle partial write\n{}", self.pos - 1, backtrace);
+        panic!("the write call at position {} didn't handle partial write", self.last_write_pos);
+    }
+
+    /// Checks that all expected bytes were written once the closure returns.
+    fn check_complete(&self, expected_len: usize, config: &Config<'_>) {
+        let expected_len = config.capacity.map_or(expected_len, |capacity| capacity.min(expected_len));
+        if self.pos < expected_len {
+            // the unwritten data may include trailing bytes past the expected ones
+            if self.last_unwritten >= expected_len - self.pos {
+                self.emit_unhandled_partial_write();
+            } else {
+                panic!("too few bytes were written to the writer but it seems unrelated to partial writes");
+            }
+        }
+        if config.require_flush && self.pos > 0 && self.flush_offsets.last() != Some(&self.pos) {
+            panic!("writer dropped without flushing after writing {} bytes", self.pos);
+        }
     }
 
-    /// Must be called before a backtrace is displayed for the first time
-    fn resolve_backtrace(&mut self) {
-        crate::backtrace_impl::resolve(&mut self.last_call);
+    /// Records a `flush` call at the current position.
+    fn flush(&mut self) {
+        self.flush_offsets.push(self.pos);
     }
 }
 
 impl<'a> TestWriter<'a> {
-    fn new(expected: &'a [u8], stats: &'a mut WriteStats) -> Self {
+    fn breaking(expected: Expected<'a>, config: &'a Config<'a>, stats: &'a mut WriteStats) -> Self {
+        config.trace(IoEvent::Run { split: None, });
+        TestWriter {
+            expected,
+            all_expected: expected,
+            stats,
+            mode: Mode::Breaking,
+            config,
+        }
+    }
+
+    fn searching(expected: Expected<'a>, config: &'a Config<'a>, stats: &'a mut WriteStats, split: usize, backtrace: BacktraceStorageMut<'a>) -> Self {
+        config.trace(IoEvent::Run { split: Some(split), });
+        TestWriter {
+            expected,
+            all_expected: expected,
+            stats,
+            mode: Mode::Searching { split, backtrace, },
+            config,
+        }
+    }
+
+    /// Accepts the first `greedy` bytes at once (as much as each write provides), then breaks.
+    fn greedy(expected: Expected<'a>, config: &'a Config<'a>, stats: &'a mut WriteStats, greedy: usize) -> Self {
+        config.trace(IoEvent::Run { split: None, });
+        TestWriter {
+            expected,
+            all_expected: expected,
+            stats,
+            mode: Mode::Greedy { greedy, },
+            config,
+        }
+    }
+
+    fn whole(expected: Expected<'a>, config: &'a Config<'a>, stats: &'a mut WriteStats) -> Self {
+        config.trace(IoEvent::Run { split: None, });
         TestWriter {
             expected,
+            all_expected: expected,
             stats,
+            mode: Mode::Whole,
+            config,
         }
     }
 
-    fn offset_data_matches(&self, data: &[u8]) -> bool {
-        // shorten the code
-        let last_unwritten = self.stats.last_unwritten;
-        last_unwritten + data.len() <= self.expected.len() &&
-            self.expected[last_unwritten..(last_unwritten + data.len())] == *data
+    /// Detects consumers resending the same data after partial writes
+    fn check_progress(&mut self, data: &[u8]) {
+        if self.stats.last_unwritten > 0 && self.stats.last_partial_data == data {
+            self.stats.repeated_writes += 1;
+            assert!(self.stats.repeated_writes <= self.config.max_repeated_writes, "writer made no progress, likely resending the same data");
+        } else {
+            self.stats.repeated_writes = 0;
+        }
     }
 
     /// Checks that data to be written is expected
     fn check_write(&mut self, data: &[u8]) {
         assert!(data.len() <= self.expected.len(), "attempt to write more data than expected");
         assert_ne!(data.len(), 0, "attempt to write 0 bytes to the writer; probably unrelated to splitting");
-        let expected = &self.expected[..data.len()];
-        if data != expected {
-            self.stats.resolve_backtrace();
-            if self.offset_data_matches(data) {
+        if !self.expected.matches(0, data) {
+            if self.expected.matches(self.stats.last_unwritten, data) {
                 self.stats.emit_unhandled_partial_write();
             } else {
-                let backtrace = DisplayBacktrace::write(&self.stats.last_call);
-                panic!("attempt to write unexpected data at pos {}, probably unrelated to partial writes\nexpected: {:?}\nreceived: {:?}\n{}", self.stats.pos, &self.expected[..data.len()], data, backtrace);
+                let expected = self.expected.get(0, data.len());
+                let diff = HexDiff::new(&expected, data, self.stats.pos);
+                panic!("attempt to write unexpected data at pos {}, probably unrelated to partial writes\naccepted so far: {:?}\n{}", self.stats.pos, TruncatedTail(&self.accepted_tail()), diff);
             }
         }
     }
-}
 
-impl Write for TestWriter<'_> {
+    /// Returns the last few accepted bytes, one more than displayed so that `TruncatedTail` knows
+    /// there's more
+    fn accepted_tail(&self) -> Cow<'a, [u8]> {
+        let start = self.stats.pos.saturating_sub(TRUNCATED_LEN + 1);
+        self.all_expected.get(start, self.stats.pos - start)
+    }
+
+    /// Checks that the bytes written past the expected data are valid padding and marks them as
+    /// written
+    fn accept_padding(&mut self, padding: &[u8]) {
+        if let Some(valid) = &self.config.trailing {
+            if let Some(invalid) = padding.iter().position(|byte| !valid(*byte)) {
+                panic!("trailing byte {:#04x} at pos {} is not valid padding", padding[invalid], self.stats.pos + invalid);
+            }
+        }
+        if let Some(written) = &mut self.stats.written {
+            written.extend_from_slice(padding);
+        }
+        self.stats.pos += padding.len();
+        self.stats.last_unwritten = 0;
+    }
+
+    /// Performs the write without counting the call, used by `write` and `write_all`
     #[cfg_attr(feature = "rust_1_46", track_caller)]
-    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        self.check_write(data);
-        if data.len() == 1 {
-            // Erase backtrace since this is correct usage
-            self.stats.last_call = None;
+    fn write_data(&mut self, offered: &[u8]) -> io::Result<usize> {
+        if self.is_full() && !offered.is_empty() {
+            self.stats.full_writes += 1;
+            assert!(self.stats.full_writes <= self.config.max_repeated_writes, "consumer kept writing after the writer returned `Ok(0)` signaling it's full");
+            self.stats.write_sizes.push(offered.len());
+            return Ok(0);
+        }
+        self.check_progress(offered);
+        if self.expected.len() == 0 && self.config.trailing.is_some() && !offered.is_empty() {
+            self.stats.write_sizes.push(offered.len());
+            // the padding counts towards the capacity too
+            let accepted = match self.config.capacity {
+                Some(capacity) => offered.len().min(capacity - self.stats.pos),
+                None => offered.len(),
+            };
+            self.accept_padding(&offered[..accepted]);
+            self.stats.last_unwritten = offered.len() - accepted;
+            return Ok(accepted);
+        }
+        // A bounded writer accepts at most the expected bytes of over-long data, which is fine if
+        // the consumer handles the short write, so only the data that can be accepted is checked.
+        let data = if offered.len() > self.expected.len() && self.expected.len() > 0 {
+            &offered[..self.expected.len()]
         } else {
-            BacktraceStorageMut::from_mut(&mut self.stats.last_call).capture();
+            offered
+        };
+        self.check_write(data);
+        self.stats.write_sizes.push(offered.len());
+        let accepted = match &mut self.mode {
+            Mode::Breaking => split::breaking_len(0, data.len()),
+            Mode::Searching { split, backtrace } => {
+                match split::split_len(self.stats.pos, *split, data.len()) {
+                    Some(len) => {
+                        // if there is a problem it's caused by function that called `write` at
+                        // the moment it split - now. We don't know if there actually is a
+                        // problem for this specific split, so we collect backtrace and decide
+                        // later whether to keep it.
+                        backtrace.capture();
+                        len
+                    },
+                    None => data.len(),
+                }
+            },
+            Mode::Greedy { greedy } => {
+                let accepted = split::breaking_len(*greedy, data.len());
+                *greedy = greedy.saturating_sub(accepted);
+                accepted
+            },
+            Mode::Whole => data.len(),
+        };
+        let accepted = match self.config.capacity {
+            Some(capacity) => accepted.min(capacity - self.stats.pos),
+            None => accepted,
+        };
+        if accepted < offered.len() {
+            self.stats.last_write_pos = self.stats.pos;
+            self.stats.last_partial_data.clear();
+            self.stats.last_partial_data.extend_from_slice(offered);
         }
-        self.stats.last_unwritten = data.len() - 1;
-        self.stats.pos += 1;
-        self.expected = &self.expected[1..];
-        Ok(1)
+        self.stats.last_unwritten = offered.len() - accepted;
+        self.advance(accepted);
+        Ok(accepted)
     }
 
-    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
-        self.check_write(data);
-        self.stats.last_unwritten = 0;
-        // Erase backtrace since this is correct usage
-        self.stats.last_call = None;
-        self.stats.pos += data.len();
-        self.expected = &self.expected[data.len()..];
+    /// Returns `true` if the writer reached its capacity and accepts no more data.
+    fn is_full(&self) -> bool {
+        self.config.capacity.map_or(false, |capacity| self.stats.pos >= capacity)
+    }
+
+    /// Marks `len` bytes as written
+    fn advance(&mut self, len: usize) {
+        if let Some(written) = &mut self.stats.written {
+            // the data was checked to match so there's no need to pass it around
+            written.extend_from_slice(&self.expected.get(0, len));
+        }
+        self.stats.pos += len;
+        self.expected.advance(len);
+    }
+}
+
+impl TestWriter<'_> {
+    /// Returns the number of expected bytes that were not written yet.
+    ///
+    /// Only accepted bytes count as written, so after a partial write this includes the rest of
+    /// the data passed to it. This is useful for checking invariants of the encoder, e.g. that
+    /// exactly the length of the payload remains after writing the header.
+    pub fn remaining(&self) -> usize {
+        self.expected.len()
+    }
+}
+
+impl fmt::Debug for TestWriter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Breaking => "breaking",
+            Mode::Searching { .. } => "searching",
+            Mode::Greedy { .. } => "greedy",
+            Mode::Whole => "whole",
+        };
+        let mut debug = f.debug_struct("TestWriter");
+        debug.field("mode", &mode);
+        if let Mode::Searching { split, .. } = self.mode {
+            debug.field("split", &split);
+        }
+        debug
+            .field("pos", &self.stats.pos)
+            .field("last_unwritten", &self.stats.last_unwritten)
+            .field("remaining", &self.expected.len())
+            // one more byte than displayed so that `Truncated` knows there are more
+            .field("upcoming", &Truncated(&self.expected.get(0, self.expected.len().min(TRUNCATED_LEN + 1))))
+            .finish()
+    }
+}
+
+impl Write for TestWriter<'_> {
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn write(&mut self, offered: &[u8]) -> io::Result<usize> {
+        self.stats.write_calls += 1;
+        let pos = self.stats.pos;
+        let accepted = self.write_data(offered)?;
+        self.config.trace(IoEvent::Write { pos, offered: offered.len(), accepted, });
+        Ok(accepted)
+    }
+
+    // loops on `write` like std does so that the data is split just like with other writers
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        self.stats.write_all_calls += 1;
+        let (pos, len) = (self.stats.pos, data.len());
+        let padding = match self.config.trailing {
+            Some(_) => data.len().saturating_sub(self.expected.len()),
+            None => 0,
+        };
+        let checked = &data[..(data.len() - padding)];
+        if padding == 0 || !checked.is_empty() {
+            self.check_write(checked);
+        }
+        // a full writer has to return `Ok(0)` from the looped `write`
+        let exceeds_capacity = self.config.capacity.map_or(false, |capacity| pos + len > capacity);
+        if self.config.whole_write_all && !exceeds_capacity {
+            self.stats.write_sizes.push(data.len());
+            self.stats.last_unwritten = 0;
+            self.advance(checked.len());
+            self.accept_padding(&data[checked.len()..]);
+            self.config.trace(IoEvent::WriteAll { pos, len, });
+            return Ok(());
+        }
+        while !data.is_empty() {
+            let written = self.write_data(data)?;
+            if written == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer"));
+            }
+            data = &data[written..];
+        }
+        self.config.trace(IoEvent::WriteAll { pos, len, });
         Ok(())
     }
 
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
     fn flush(&mut self) -> io::Result<()> {
+        let pos = self.stats.pos;
+        if let Some(legal) = self.config.legal_flush_offsets {
+            if !legal.contains(&pos) {
+                // the flush is the culprit rather than the write crossing the split
+                if let Mode::Searching { backtrace, .. } = &mut self.mode {
+                    backtrace.capture();
+                }
+                panic!("illegal flush at offset {}, flushing is only allowed at offsets {:?}", pos, legal);
+            }
+        }
+        self.stats.flush();
+        self.config.trace(IoEvent::Flush { pos, });
         Ok(())
     }
 }
 
-pub(crate) mod hack {
-    use super::TestWriter;
-    use std::panic::{UnwindSafe, RefUnwindSafe};
+/// Configurable test of `Write` consumers.
+///
+/// This allows checking additional properties of the written data. [`test_write`] is equivalent to
+/// `WriteTest::new(expected).run(f)`.
+///
+/// [`test_write`]: super::test_write
+pub struct WriteTest<'a> {
+    expected: ExpectedSource<'a>,
+    write_sizes: Option<&'a [usize]>,
+    flush_offsets: Option<&'a [usize]>,
+    config: Config<'a>,
+    reporter: &'a dyn Reporter,
+}
 
-    /// Tests whether the closure correctly handles partial writes.
+/// Settings of the test shared by all writers
+#[derive(Clone)]
+struct Config<'a> {
+    max_repeated_writes: usize,
+    // offsets at which `flush` may be called, any if `None`
+    legal_flush_offsets: Option<&'a [usize]>,
+    sweep_first_write: bool,
+    allow_unused_writer: bool,
+    normalize_message: Option<NormalizeFn>,
+    // skip running the closure again to check that it writes the same data
+    allow_nondeterministic: bool,
+    // additionally test with `whole_write_all` enabled
+    both_write_paths: bool,
+    // accept the data of `write_all` at once instead of looping on the breaking `write`
+    whole_write_all: bool,
+    // accepts bytes written past the expected data
+    trailing: Option<Arc<TrailingFn>>,
+    // called for each IO call of the closure
+    trace: Option<Arc<TraceFn>>,
+    // the closure has to flush after the last write
+    require_flush: bool,
+    // number of bytes after which `write` returns `Ok(0)`
+    capacity: Option<usize>,
+}
+
+impl Config<'_> {
+    fn trace(&self, event: IoEvent) {
+        if let Some(trace) = &self.trace {
+            trace(event);
+        }
+    }
+}
+
+/// Checks whether a byte written past the expected data is valid padding.
+type TrailingFn = dyn Fn(u8) -> bool + Send + Sync + RefUnwindSafe;
+
+impl<'a> WriteTest<'a> {
+    /// Creates the test expecting the closure to write `expected` bytes.
+    pub fn new(expected: &'a [u8]) -> Self {
+        Self::with_expected(ExpectedSource::Borrowed(Expected::Slice(expected)))
+    }
+
+    /// Creates the test expecting the closure to write `len` bytes returned by `generator`.
     ///
-    /// This is the entry point of this crate for write testing.
-    /// You provide `expected` - bytes that should be produced by your closure, and a closure that
-    /// accepts a writer and writes to it.
+    /// `generator` is called with the offset of each byte and must return the byte expected at
+    /// that offset. This avoids storing huge expected outputs in memory at the cost of calling
+    /// `generator` for each written byte, possibly multiple times.
+    pub fn generated<G>(len: usize, generator: &'a G) -> Self where G: Fn(usize) -> u8 + RefUnwindSafe {
+        Self::with_expected(ExpectedSource::Borrowed(Expected::Generated { start: 0, end: len, generator, }))
+    }
+
+    /// Creates the test expecting the closure to write `len` bytes read from `make_reader()`.
     ///
-    /// For best results make sure no other inputs affect the test - the function should be pure.
-    /// It is currently called once but it may be called multiple times in the future.
+    /// This allows verifying very large or lazily-produced outputs without keeping them in
+    /// memory. The expected bytes are read as the closure writes and only the bytes of the current
+    /// write are buffered. The closure is called repeatedly and writes from the beginning each
+    /// time, so `make_reader` is called to create a fresh reader whenever that happens. The
+    /// readers must produce the same data each time.
     ///
-    /// The function may report the culprit incorrectly - in that case the problem is *before*
-    /// reported function call. There's a plan to improve this in the future.
-    pub fn test_write<F>(expected: &[u8], f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
-        super::test_write(expected, f);
+    /// `len` is required upfront since the search needs to know all split positions. The test
+    /// panics if a reader ends before `len` bytes, bytes after `len` are not checked. Writing more
+    /// than `len` bytes or fewer fails the test just like with [`new`](Self::new).
+    pub fn streamed<F, R>(len: usize, make_reader: F) -> Self where F: Fn() -> R + RefUnwindSafe + 'a, R: Read + 'a {
+        let stream = ExpectedStream {
+            make_reader,
+            window: AssertUnwindSafe(RefCell::new(Window { reader: None, start: 0, buf: Vec::new(), })),
+        };
+        Self::with_expected(ExpectedSource::Streamed { len, stream: Box::new(stream), })
     }
-}
 
-fn test_write<F>(expected: &[u8], f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
-    let mut stats = WriteStats::default();
-    f(TestWriter::new(expected, &mut stats));
-    if stats.pos < expected.len() {
-        stats.resolve_backtrace();
-        if stats.last_unwritten == expected.len() - stats.pos {
-            stats.emit_unhandled_partial_write();
-        } else {
-            let backtrace = DisplayBacktrace::write(&stats.last_call);
-            panic!("too few bytes were written to the writer but it seems unrelated to partial writes\n{}", backtrace);
+    fn with_expected(expected: ExpectedSource<'a>) -> Self {
+        WriteTest {
+            expected,
+            write_sizes: None,
+            flush_offsets: None,
+            config: Config {
+                max_repeated_writes: 1024,
+                legal_flush_offsets: None,
+                sweep_first_write: false,
+                allow_unused_writer: false,
+                normalize_message: None,
+                allow_nondeterministic: false,
+                both_write_paths: false,
+                whole_write_all: false,
+                trailing: None,
+                trace: None,
+                require_flush: false,
+                capacity: None,
+            },
+            reporter: &PanicReporter,
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::hack::test_write;
-    use std::io::Write;
+    /// Sets the number of times the same data may be resent after a partial write.
+    ///
+    /// A consumer that ignores the number of written bytes and resends the same data would loop
+    /// forever on a writer that makes no progress, so the writer panics instead. The default is
+    /// 1024.
+    pub fn max_repeated_writes(mut self, max: usize) -> Self {
+        self.config.max_repeated_writes = max;
+        self
+    }
 
-    #[test]
-    fn empty() {
-        test_write(&[], |_| ());
+    /// Allows the closure to not write to the writer at all if nothing is expected.
+    ///
+    /// A closure that never writes passes trivially when `expected` is empty, so by default the
+    /// test panics in such case. This option disables the check for intentionally empty closures.
+    pub fn allow_unused_writer(mut self) -> Self {
+        self.config.allow_unused_writer = true;
+        self
     }
 
-    #[test]
-    #[should_panic = "attempt to write more data than expected"]
-    fn empty_write_past_end() {
-        test_write(&[], |mut writer| { writer.write(&[42]).unwrap(); });
+    /// Additionally tests the closure with writers accepting a larger chunk first.
+    ///
+    /// Some bugs only appear when the first writes are accepted whole but a later one is partial -
+    /// e.g. a retry loop that computes the offset correctly only for the first chunk. If this is
+    /// enabled and the usual test passes the closure is called again with writers accepting the
+    /// first `k` bytes whole and then one byte per call, for each `k` from 2 to
+    /// `expected.len() - 1`. The failure message reports `k`.
+    pub fn sweep_first_write(mut self) -> Self {
+        self.config.sweep_first_write = true;
+        self
     }
 
-    #[test]
-    #[should_panic = "attempt to write more data than expected"]
-    fn empty_write_all_past_end() {
-        test_write(&[], |mut writer| writer.write_all(&[42]).unwrap());
+    /// Skips the check that the closure writes the same data when run again.
+    ///
+    /// The closure has to be pure for the search to make sense, so once the byte-by-byte pass
+    /// passes it's called again with a writer accepting whole writes. The test fails if this run
+    /// fails, e.g. because the output includes a timestamp, or with "encoder output is
+    /// non-deterministic across runs" if both runs complete but the trailing bytes accepted by
+    /// [`allow_trailing`](Self::allow_trailing) differ. This option disables the additional run
+    /// for closures that are intentionally impure or depend on how the writer splits the data.
+    pub fn allow_nondeterministic(mut self) -> Self {
+        self.config.allow_nondeterministic = true;
+        self
     }
 
-    #[test]
-    #[should_panic = "too few bytes were written to the writer but it seems unrelated to partial writes"]
-    fn one_byte_not_enoug() {
-        test_write(&[42], |_| ());
+    /// Additionally tests the closure with `write_all` accepting whole data.
+    ///
+    /// By default `write_all` loops on the breaking `write` just like the default implementation
+    /// in `std` does, so both methods are split. If this is enabled and the usual test passes the
+    /// closure is tested again with a writer splitting only `write` calls and accepting the data
+    /// of `write_all` at once, like writers overriding it (e.g. `Vec<u8>`) do. This stresses retry
+    /// loops around `write` separately, which matters for encoders choosing between the methods
+    /// depending on the data. Failures of this variant are reported as such.
+    pub fn both_write_paths(mut self) -> Self {
+        self.config.both_write_paths = true;
+        self
     }
 
-    #[test]
-    fn one_byte_write() {
-        test_write(&[42], |mut writer| { writer.write(&[42]).unwrap(); });
+    /// Accepts bytes written past `expected` if they satisfy the predicate.
+    ///
+    /// Some encoders pad their output, e.g. to a block boundary, and it's often easier to not
+    /// include the padding in `expected`. With this option any bytes written after all expected
+    /// bytes are accepted whole as long as `valid` returns `true` for each of them, otherwise the
+    /// test fails reporting the invalid byte. Partial writes are still performed and checked
+    /// within the expected data.
+    pub fn allow_trailing<P>(mut self, valid: P) -> Self where P: Fn(u8) -> bool + Send + Sync + RefUnwindSafe + 'static {
+        self.config.trailing = Some(Arc::new(valid));
+        self
     }
 
-    #[test]
-    fn one_byte_write_all() {
-        test_write(&[42], |mut writer| writer.write_all(&[42]).unwrap());
+    /// Calls the closure for each IO call the tested closure makes.
+    ///
+    /// Works the same as [`ReadTest::trace`](crate::read::ReadTest::trace). The `write` calls
+    /// made by `write_all` internally are not traced, only the `write_all` call itself.
+    pub fn trace<T>(mut self, tracer: T) -> Self where T: Fn(IoEvent) + Send + Sync + RefUnwindSafe + 'static {
+        self.config.trace = Some(Arc::new(tracer));
+        self
     }
 
-    #[test]
-    #[should_panic = "attempt to write more data than expected"]
-    fn one_byte_write_past_end() {
-        test_write(&[42], |mut writer| { writer.write(&[42, 47]).unwrap(); });
+    /// Requires the closure to call `write` or `write_all` with data of exactly these lengths.
+    ///
+    /// This verifies batching behavior, not handling of partial writes: the sizes are recorded in
+    /// a separate run using a writer that accepts whole writes, so retries of partial writes are
+    /// not recorded.
+    pub fn expect_write_sizes(mut self, sizes: &'a [usize]) -> Self {
+        self.write_sizes = Some(sizes);
+        self
     }
 
-    #[test]
-    #[should_panic = "attempt to write more data than expected"]
-    fn one_byte_write_all_past_end() {
-        test_write(&[42], |mut writer| writer.write_all(&[42, 47]).unwrap());
+    /// Requires the closure to call `flush` exactly when these numbers of bytes were written.
+    ///
+    /// Some protocols require flushing at frame boundaries, flushing in the middle of a frame is a
+    /// bug there. Just like with [`expect_write_sizes`](Self::expect_write_sizes) the offsets are
+    /// recorded in a separate run using a writer that accepts whole writes.
+    pub fn expect_flush_offsets(mut self, offsets: &'a [usize]) -> Self {
+        self.flush_offsets = Some(offsets);
+        self
     }
 
-    #[test]
-    #[should_panic = "attempt to write more data than expected"]
-    fn one_byte_write_past_end_two_writes() {
-        test_write(&[42], |mut writer| {
-            writer.write(&[42]).unwrap();
-            writer.write(&[47]).unwrap();
-        });
+    /// Simulates a sink that becomes full after accepting `capacity` bytes.
+    ///
+    /// Once `capacity` bytes were written `write` returns `Ok(0)` and `write_all` fails with
+    /// `WriteZero`, just like a fixed buffer would. This is different from partial writes, which
+    /// still make progress: the closure has to stop writing or return an error. Only the first
+    /// `capacity` bytes of `expected` have to be written then. A closure ignoring `Ok(0)` and
+    /// writing again fails the test once it tries more than
+    /// [`max_repeated_writes`](Self::max_repeated_writes) times instead of looping forever.
+    ///
+    /// Capacity not smaller than the length of `expected` only limits the trailing bytes accepted
+    /// by [`allow_trailing`](Self::allow_trailing).
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.config.capacity = Some(capacity);
+        self
     }
 
-    #[test]
-    #[should_panic = "attempt to write more data than expected"]
-    fn one_byte_write_past_end_two_write_all() {
-        test_write(&[42], |mut writer| {
-            writer.write_all(&[42]).unwrap();
-            writer.write_all(&[47]).unwrap();
-        });
+    /// Requires the closure to call `flush` after the last write.
+    ///
+    /// Buffered writers can not report errors from `Drop`, so the data may be lost silently if
+    /// the code writes and then drops the writer without flushing it. With this option the test
+    /// fails if the closure returns without flushing after writing data. The check applies to all
+    /// runs so a flush skipped only after a partial write is caught too.
+    pub fn require_flush(mut self) -> Self {
+        self.config.require_flush = true;
+        self
     }
 
-    #[test]
-    fn two_byte_write_all() {
-        test_write(&[42, 47], |mut writer| writer.write_all(&[42, 47]).unwrap());
+    /// Fails the test as soon as the closure calls `flush` at any other offset than these.
+    ///
+    /// Unlike [`expect_flush_offsets`](Self::expect_flush_offsets) this doesn't require any
+    /// flushes, it only forbids them at wrong positions, e.g. in the middle of a frame. The check
+    /// applies to all runs so a flush misplaced only after a partial write is caught too and the
+    /// reported culprit is the `flush` call.
+    pub fn legal_flush_offsets(mut self, offsets: &'a [usize]) -> Self {
+        self.config.legal_flush_offsets = Some(offsets);
+        self
     }
 
-    #[test]
-    fn two_bytes_two_writes() {
-        test_write(&[42, 47], |mut writer| {
-            writer.write(&[42]).unwrap();
-            writer.write(&[47]).unwrap();
-        });
+    /// Normalizes panic messages using the function before comparing them.
+    ///
+    /// Works the same as [`ReadTest::normalize_messages`](crate::read::ReadTest::normalize_messages).
+    pub fn normalize_messages(mut self, normalize: fn(&str) -> String) -> Self {
+        self.config.normalize_message = Some(normalize);
+        self
     }
 
-    #[test]
-    fn two_bytes_two_write_all() {
-        test_write(&[42, 47], |mut writer| {
-            writer.write_all(&[42]).unwrap();
-            writer.write_all(&[47]).unwrap();
-        });
+    /// Presents failures using `reporter` instead of panicking.
+    ///
+    /// This affects [`run`](Self::run) only, [`try_run`](Self::try_run) returns the failure.
+    /// Unmet expectations of write sizes and flush offsets still panic.
+    pub fn report_with(mut self, reporter: &'a dyn Reporter) -> Self {
+        self.reporter = reporter;
+        self
     }
 
-    #[test]
-    #[should_panic = "the write call at position 0 didn't handle partial write"]
-    fn two_bytes_unhandled_partial() {
-        test_write(&[42, 47], |mut writer| {
-            writer.write(&[42, 47]).unwrap();
-        });
+    /// Runs the test, panicking if it fails.
+    ///
+    /// See [`test_write`] for more information.
+    ///
+    /// [`test_write`]: super::test_write
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run<F>(self, f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        if let Err(failure) = self.try_run(f) {
+            self.reporter.on_write_failure(failure);
+        }
     }
 
-    #[test]
-    #[should_panic = "the write call at position 0 didn't handle partial write"]
-    fn three_bytes_unhandled_partial() {
-        test_write(&[42, 47, 1], |mut writer| {
-            writer.write(&[42, 47]).unwrap();
-            writer.write_all(&[1]).unwrap();
-        });
+    /// Runs the test with a closure returning `io::Result`, panicking if it fails.
+    ///
+    /// This allows using `?` inside the closure. An error returned by the closure is reported
+    /// along with its message, distinguishing it from the closure returning `Ok` without writing
+    /// all the expected bytes, which is reported as too few bytes written.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn run_fallible<F>(self, f: F) where F: Fn(TestWriter<'_>) -> io::Result<()> + UnwindSafe + RefUnwindSafe {
+        self.run(|writer| {
+            if let Err(error) = f(writer) {
+                panic!("the closure returned an IO error instead of writing all data: {}", error);
+            }
+        })
+    }
+
+    /// Runs the test, returning the failure instead of panicking.
+    ///
+    /// On success the bytes accepted by the writer are returned so that additional checks can be
+    /// performed, e.g. when `expected` is derived rather than literal. Expectations set by
+    /// [`expect_write_sizes`](Self::expect_write_sizes) and
+    /// [`expect_flush_offsets`](Self::expect_flush_offsets) still panic if not met.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn try_run<F>(&self, f: F) -> Result<Vec<u8>, WriteFailure> where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        let written = self.run_no_panic(&f).map_err(WriteFailure)?;
+
+        if self.write_sizes.is_some() || self.flush_offsets.is_some() {
+            self.run_whole(&f);
+        }
+        Ok(written)
+    }
+
+    /// Runs the test, returning the failure or a summary of the successful test.
+    ///
+    /// This works like [`try_run`](Self::try_run) but on success the closure is called once more
+    /// with a writer accepting whole writes to count the calls of each method. Unlike the
+    /// partial writes of the test itself these reflect what the closure does with a writer that
+    /// makes progress, e.g. to check that an encoder doesn't flush or writes using few calls.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn try_run_summary<F>(&self, f: F) -> Result<WriteSummary, WriteFailure> where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        let written = self.run_no_panic(&f).map_err(WriteFailure)?;
+        let stats = self.run_whole(&f);
+        Ok(WriteSummary {
+            written,
+            pos: stats.pos,
+            last_unwritten: stats.last_unwritten,
+            write_sizes: stats.write_sizes,
+            write_calls: stats.write_calls,
+            write_all_calls: stats.write_all_calls,
+            flush_offsets: stats.flush_offsets,
+        })
+    }
+
+    /// Runs the closure with a writer accepting whole writes and checks the expectations.
+    fn run_whole<F>(&self, f: F) -> WriteStats where F: Fn(TestWriter<'_>) {
+        let mut stats = WriteStats::default();
+        let expected = self.expected.get();
+        f(TestWriter::whole(expected, &self.config, &mut stats));
+        stats.check_complete(expected.len(), &self.config);
+        if let Some(expected_sizes) = self.write_sizes {
+            assert!(stats.write_sizes == expected_sizes, "unexpected sizes of writes, expected: {:?}, observed: {:?}", expected_sizes, stats.write_sizes);
+        }
+        if let Some(expected_offsets) = self.flush_offsets {
+            assert!(stats.flush_offsets == expected_offsets, "unexpected offsets of flushes, expected: {:?}, observed: {:?}", expected_offsets, stats.flush_offsets);
+        }
+        stats
+    }
+
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    fn run_no_panic<F>(&self, f: F) -> Result<Vec<u8>, Error> where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        let expected = self.expected.get();
+        let config = &self.config;
+        let location = backtrace_impl::test_location();
+        let result = test_splits(expected, config, location, &f);
+        if let Ok(stats) = &result {
+            if stats.write_sizes.is_empty() && expected.len() == 0 && !config.allow_unused_writer {
+                return Err(Error::new(UnlocalizedFailure::unwind("test closure never wrote to the provided writer - the test is not exercising anything"), Operation::Write, location, config.normalize_message));
+            }
+        }
+        if let (Ok(stats), false) = (&result, config.allow_nondeterministic) {
+            let rerun = error::quiet_panics(|| catch_unwind(|| {
+                let mut stats = WriteStats { written: Some(Vec::new()), ..Default::default() };
+                f(TestWriter::whole(expected, config, &mut stats));
+                stats.check_complete(expected.len(), config);
+                stats.written
+            }));
+            let failure = match rerun {
+                Ok(written) if written == stats.written => None,
+                Ok(_) => Some((UnlocalizedFailure::unwind("encoder output is non-deterministic across runs, the trailing bytes differ"), false)),
+                Err(unwind) => Some((unwind, true)),
+            };
+            if let Some((unwind, whole_rerun)) = failure {
+                return Err(Error {
+                    whole_rerun,
+                    ..Error::new(unwind, Operation::Write, location, config.normalize_message)
+                });
+            }
+        }
+        if result.is_ok() && config.both_write_paths {
+            let config = Config { whole_write_all: true, ..config.clone() };
+            test_splits(expected, &config, location, &f)?;
+        }
+        if result.is_ok() && config.sweep_first_write {
+            let failure = error::quiet_panics(|| (2..expected.len()).find_map(|first_chunk| {
+                catch_unwind(|| {
+                    let mut stats = WriteStats::default();
+                    f(TestWriter::greedy(expected, config, &mut stats, first_chunk));
+                    stats.check_complete(expected.len(), config);
+                }).err().map(|unwind| (first_chunk, unwind))
+            }));
+            if let Some((first_chunk, unwind)) = failure {
+                return Err(Error {
+                    first_chunk: Some(first_chunk),
+                    ..Error::new(unwind, Operation::Write, location, config.normalize_message)
+                });
+            }
+        }
+        result.map(|stats| stats.written.unwrap_or_default())
+    }
+}
+
+/// Runs the closure with the breaking writer and searches for the failing position if it fails.
+fn test_splits<F>(expected: Expected<'_>, config: &Config<'_>, location: TestLocation, f: &F) -> Result<WriteStats, Error> where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+    catch_unwind(|| {
+        let mut stats = WriteStats { written: Some(Vec::new()), ..Default::default() };
+        f(TestWriter::breaking(expected, config, &mut stats));
+        stats.check_complete(expected.len(), config);
+        stats
+    })
+    .map_err(|unwind| {
+        let attempt = |pos, backtrace_mut: BacktraceStorageMut<'_>| {
+            catch_unwind(|| {
+                let mut stats = WriteStats::default();
+                f(TestWriter::searching(expected, config, &mut stats, pos, backtrace_mut));
+                stats.check_complete(expected.len(), config);
+            })
+        };
+        let mut failure_info = error::quiet_panics(|| error::search(expected.len(), &attempt));
+        // a different message means there are likely two bugs, so the other one is localized too
+        if let Some(info) = &mut failure_info {
+            if !error::same_message(&unwind, &info.unwind, config.normalize_message) {
+                info.breaking_pos = error::quiet_panics(|| ((info.pos + 1)..expected.len()).find(|pos| {
+                    let mut backtrace = None;
+                    match attempt(*pos, BacktraceStorageMut::from_mut(&mut backtrace)) {
+                        Ok(()) => false,
+                        Err(failure) => error::same_message(&failure, &unwind, config.normalize_message),
+                    }
+                }));
+            }
+        }
+        Error {
+            failure_info,
+            whole_write_all: config.whole_write_all,
+            ..Error::new(unwind, Operation::Write, location, config.normalize_message)
+        }
+    })
+}
+
+/// Information about a successful write test.
+///
+/// Returned by [`WriteTest::try_run_summary`] and [`try_test_write_summary`]. Except for the
+/// written bytes the values are recorded with a writer accepting whole writes.
+///
+/// [`try_test_write_summary`]: super::try_test_write_summary
+#[derive(Debug, Clone)]
+pub struct WriteSummary {
+    written: Vec<u8>,
+    pos: usize,
+    last_unwritten: usize,
+    write_sizes: Vec<usize>,
+    write_calls: usize,
+    write_all_calls: usize,
+    flush_offsets: Vec<usize>,
+}
+
+impl WriteSummary {
+    /// Returns the bytes accepted by the writer.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Returns the position of the writer after the closure returned.
+    ///
+    /// This is the number of accepted bytes including trailing padding if it was allowed.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns the number of bytes the last write call didn't accept.
+    ///
+    /// The writer accepts whole writes when recording the summary, so this is non-zero only if
+    /// the last call was a partial write of over-long data.
+    pub fn last_unwritten(&self) -> usize {
+        self.last_unwritten
+    }
+
+    /// Returns the lengths of data passed to `write` and `write_all` calls, in order.
+    pub fn write_sizes(&self) -> &[usize] {
+        &self.write_sizes
+    }
+
+    /// Returns the number of `write` calls.
+    pub fn write_calls(&self) -> usize {
+        self.write_calls
+    }
+
+    /// Returns the number of `write_all` calls.
+    ///
+    /// The `write` calls performed by `write_all` are not counted in
+    /// [`write_calls`](Self::write_calls).
+    pub fn write_all_calls(&self) -> usize {
+        self.write_all_calls
+    }
+
+    /// Returns the number of `flush` calls.
+    pub fn flush_calls(&self) -> usize {
+        self.flush_offsets.len()
+    }
+
+    /// Returns the positions at which `flush` was called, in order.
+    pub fn flush_offsets(&self) -> &[usize] {
+        &self.flush_offsets
+    }
+}
+
+/// Failure of a write test.
+///
+/// Returned by [`try_test_write`] and [`WriteTest::try_run`] instead of panicking.
+///
+/// [`try_test_write`]: super::try_test_write
+#[derive(Debug)]
+pub struct WriteFailure(Error);
+
+impl WriteFailure {
+    /// Returns the split position at which the closure failed if it was found.
+    pub fn position(&self) -> Option<usize> {
+        self.0.failure_info.as_ref().map(|info| info.pos)
+    }
+
+    /// Returns the message of the panic caught in the first pass if it was a string.
+    pub fn message(&self) -> Option<&str> {
+        error::get_panic_message(&self.0.unwind)
+    }
+
+    /// Returns the payload of the panic caught in the first pass.
+    ///
+    /// This allows downcasting panics with custom payload types, e.g. raised by a test framework
+    /// using `std::panic::panic_any`, which [`message`](Self::message) can not present.
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        &*self.0.unwind
+    }
+
+    /// Returns the payload of the panic caught in the first pass, consuming the failure.
+    ///
+    /// The payload can be passed to `std::panic::resume_unwind` to continue unwinding with it.
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.0.unwind
+    }
+
+    /// Panics with the same message [`test_write`] would.
+    ///
+    /// [`test_write`]: super::test_write
+    pub fn panic(self) -> ! {
+        self.0.panic()
+    }
+
+    /// Marks the failure as found on the write side of `test_copy`.
+    pub(crate) fn copy_side(mut self) -> Self {
+        self.0.copy_side = true;
+        self
+    }
+}
+
+/// Formats the same message [`panic`](Self::panic) uses, including the culprit backtrace.
+impl fmt::Display for WriteFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Runs `f` with a writer accepting whole writes, used to test the other side of `test_copy`.
+pub(crate) fn write_whole<F>(expected: &[u8], f: F) where F: FnOnce(TestWriter<'_>) {
+    let test = WriteTest::new(expected);
+    let mut stats = WriteStats::default();
+    f(TestWriter::whole(test.expected.get(), &test.config, &mut stats));
+    stats.check_complete(expected.len(), &test.config);
+}
+
+pub(crate) mod hack {
+    use super::{write_whole, TestWriter, WriteFailure, WriteSummary, WriteTest};
+    use std::io::{self, Write};
+    use std::panic::{catch_unwind, resume_unwind, UnwindSafe, RefUnwindSafe};
+    use crate::backtrace_impl::{self, DisplayTest};
+    use crate::error;
+
+    /// Tests whether the closure correctly handles partial writes.
+    ///
+    /// This is the entry point of this crate for write testing.
+    /// You provide `expected` - bytes that should be produced by your closure, and a closure that
+    /// accepts a writer and writes to it.
+    ///
+    /// For best results make sure no other inputs affect the test - the function should be pure.
+    /// It will be called twice, the second time to check that it's deterministic, and if it panics
+    /// it'll be called again multiple times with differently-behaving writers.
+    ///
+    /// With `rust_1_46` feature the failure message contains the location of this call.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_write<F>(expected: &[u8], f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected).run(f)
+    }
+
+    /// Checks that the closure passes with a writer that doesn't split writes.
+    ///
+    /// The closure is called once with a writer accepting whole writes, just like
+    /// `std::io::Cursor` would, and has to write exactly `expected`. If it fails even then the bug
+    /// is unrelated to splitting and the panic message says so. Running this before
+    /// [`test_write`] with the same closure (pass it by reference) disambiguates "the code is
+    /// broken regardless" from "the code mishandles partial writes".
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn sanity_write<F>(expected: &[u8], f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        let location = backtrace_impl::test_location();
+        if let Err(unwind) = catch_unwind(|| write_whole(expected, f)) {
+            match error::get_panic_message(&unwind) {
+                Some(msg) => panic!("{} failed even with a writer accepting whole writes, the bug is unrelated to splitting: {}", DisplayTest(location), msg),
+                None => resume_unwind(unwind),
+            }
+        }
+    }
+
+    /// Tests whether the closure stops writing once the writer is full.
+    ///
+    /// The writer accepts only `capacity` bytes of `expected`, then `write` returns `Ok(0)`. See
+    /// [`WriteTest::capacity`] for more information.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_write_capacity<F>(expected: &[u8], capacity: usize, f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected).capacity(capacity).run(f)
+    }
+
+    /// Tests whether the closure correctly handles partial writes and flushes at the end.
+    ///
+    /// This works just like [`test_write`] but the closure also has to call `flush` after the
+    /// last write. See [`WriteTest::require_flush`] for more information.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_write_requires_flush<F>(expected: &[u8], f: F) where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected).require_flush().run(f)
+    }
+
+    /// Tests whether the closure correctly handles partial writes allowing it to return errors.
+    ///
+    /// This works just like [`test_write`] but the closure returns `io::Result` so that `?` can be
+    /// used inside it. See [`WriteTest::run_fallible`] for more information.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_write_fallible<F>(expected: &[u8], f: F) where F: Fn(TestWriter<'_>) -> io::Result<()> + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected).run_fallible(f)
+    }
+
+    /// Tests whether the closure correctly handles partial writes without panicking on failure.
+    ///
+    /// This works just like [`test_write`] but returns the failure so that it can be inspected.
+    /// On success it returns the bytes accepted by the writer.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn try_test_write<F>(expected: &[u8], f: F) -> Result<Vec<u8>, WriteFailure> where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected).try_run(f)
+    }
+
+    /// Tests whether the closure correctly handles partial writes, returning a summary on success.
+    ///
+    /// This works just like [`try_test_write`] but on success it returns a [`WriteSummary`] with
+    /// the state of the writer and the calls the closure made. See
+    /// [`WriteTest::try_run_summary`] for more information.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn try_test_write_summary<F>(expected: &[u8], f: F) -> Result<WriteSummary, WriteFailure> where F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected).try_run_summary(f)
+    }
+
+    /// Tests whether the closure correctly handles partial writes, generating the expected bytes.
+    ///
+    /// This works just like [`test_write`] but instead of storing the whole expected output
+    /// `generator` is called with the offset of each written byte and returns the expected byte.
+    /// This is useful for testing encoders producing megabytes of data. The trade-off is that
+    /// `generator` is called for each byte, possibly multiple times, so it should be cheap.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_write_with<G, F>(expected_len: usize, generator: G, f: F) where G: Fn(usize) -> u8 + RefUnwindSafe, F: Fn(TestWriter<'_>) + UnwindSafe + RefUnwindSafe {
+        WriteTest::generated(expected_len, &generator).run(f)
+    }
+
+    /// Tests whether a `Write` implementation correctly handles partial writes of its sink.
+    ///
+    /// This is the dual of [`test_write`]: instead of testing a producer of data it tests a writer
+    /// wrapping another writer, e.g. a compressor or a framing adapter. `make_writer` accepts the
+    /// sink that performs partial writes and wraps it in the writer being tested. Then `input` is
+    /// written into the wrapping writer using `write_all` and the writer is flushed. The bytes that
+    /// reached the sink have to equal `expected_inner`.
+    ///
+    /// The writer is boxed because it borrows the sink.
+    #[cfg_attr(feature = "rust_1_46", track_caller)]
+    pub fn test_writer_impl<F>(input: &[u8], expected_inner: &[u8], make_writer: F) where F: for<'a> Fn(TestWriter<'a>) -> Box<dyn Write + 'a> + UnwindSafe + RefUnwindSafe {
+        WriteTest::new(expected_inner).run(|sink| {
+            let mut writer = make_writer(sink);
+            if let Err(error) = writer.write_all(input).and_then(|_| writer.flush()) {
+                panic!("writing to the tested writer failed: {}", error);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+// the tests intentionally contain buggy IO code
+#[allow(clippy::unused_io_amount)]
+mod tests {
+    use super::hack::{sanity_write, test_write, test_write_capacity, test_write_fallible, test_write_requires_flush, test_write_with, test_writer_impl, try_test_write, try_test_write_summary};
+    use super::WriteTest;
+    use std::io::{self, Write};
+
+    #[test]
+    fn empty() {
+        WriteTest::new(&[]).allow_unused_writer().run(|_| ());
+    }
+
+    #[test]
+    #[should_panic = "test closure never wrote to the provided writer"]
+    fn empty_unused_writer() {
+        test_write(&[], |_| ());
+    }
+
+    #[test]
+    fn empty_unused_writer_try_run() {
+        let failure = WriteTest::new(&[]).try_run(|_| ()).unwrap_err();
+        assert_eq!(failure.position(), None);
+        assert_eq!(failure.message(), Some("test closure never wrote to the provided writer - the test is not exercising anything"));
+        assert!(failure.to_string().contains("failed: test closure never wrote to the provided writer"), "{}", failure);
+    }
+
+    #[test]
+    #[should_panic = "attempt to write more data than expected"]
+    fn empty_write_past_end() {
+        test_write(&[], |mut writer| { writer.write(&[42]).unwrap(); });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write more data than expected"]
+    fn empty_write_all_past_end() {
+        test_write(&[], |mut writer| writer.write_all(&[42]).unwrap());
+    }
+
+    #[test]
+    #[should_panic = "too few bytes were written to the writer but it seems unrelated to partial writes"]
+    fn one_byte_not_enoug() {
+        test_write(&[42], |_| ());
+    }
+
+    #[test]
+    fn capacity() {
+        test_write_capacity(&[1, 0, 2, 0, 3, 0], 4, |mut writer| {
+            let error = writer.write_all(&[1, 0, 2, 0, 3, 0]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+        });
+    }
+
+    #[test]
+    fn capacity_trailing() {
+        let written = WriteTest::new(&[1, 0]).capacity(3).allow_trailing(|byte| byte == 0).try_run(|mut writer| {
+            let error = writer.write_all(&[1, 0, 0, 0]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+        }).unwrap();
+        assert_eq!(written, [1, 0, 0]);
+    }
+
+    #[test]
+    fn capacity_stops_on_zero() {
+        test_write_capacity(&[1, 0, 2, 0, 3, 0], 3, |mut writer| {
+            let mut data = &[1, 0, 2, 0, 3, 0][..];
+            while !data.is_empty() {
+                match writer.write(data).unwrap() {
+                    0 => break,
+                    written => data = &data[written..],
+                }
+            }
+            assert_eq!(data, [0, 3, 0]);
+        });
+    }
+
+    #[test]
+    #[should_panic = "consumer kept writing after the writer returned `Ok(0)` signaling it's full"]
+    fn capacity_spinning() {
+        WriteTest::new(&[1, 0, 2, 0]).capacity(2).max_repeated_writes(8).run(|mut writer| {
+            let mut data = &[1, 0, 2, 0][..];
+            // buggy: treats `Ok(0)` as "try again later"
+            while !data.is_empty() {
+                let written = writer.write(data).unwrap();
+                data = &data[written..];
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 2 didn't handle partial write"]
+    fn capacity_partial_write() {
+        test_write_capacity(&[1, 0, 2, 0, 3, 0], 4, |mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            // buggy: ignores the number of written bytes
+            writer.write(&[2, 0]).unwrap();
+        });
+    }
+
+    #[test]
+    fn requires_flush() {
+        test_write_requires_flush(&[1, 0, 2, 0], |mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&[2, 0]).unwrap();
+            writer.flush().unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "writer dropped without flushing after writing 4 bytes"]
+    fn requires_flush_missing() {
+        test_write_requires_flush(&[1, 0, 2, 0], |mut writer| {
+            writer.write_all(&[1, 0, 2, 0]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "writer dropped without flushing after writing 4 bytes"]
+    fn requires_flush_after_last_write() {
+        test_write_requires_flush(&[1, 0, 2, 0], |mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            writer.flush().unwrap();
+            // buggy: the last frame is not flushed
+            writer.write_all(&[2, 0]).unwrap();
+        });
+    }
+
+    #[test]
+    fn trace() {
+        use std::sync::{Arc, Mutex};
+        use crate::trace::IoEvent;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        WriteTest::new(&[1, 0, 2, 0]).trace(move |event| recorded.lock().unwrap().push(event)).run(|mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            writer.flush().unwrap();
+            let mut data = &[2, 0][..];
+            while !data.is_empty() {
+                let written = writer.write(data).unwrap();
+                data = &data[written..];
+            }
+        });
+        let events = events.lock().unwrap();
+        let expected = [
+            IoEvent::Run { split: None, },
+            IoEvent::WriteAll { pos: 0, len: 2, },
+            IoEvent::Flush { pos: 2, },
+            IoEvent::Write { pos: 2, offered: 2, accepted: 1, },
+            IoEvent::Write { pos: 3, offered: 1, accepted: 1, },
+        ];
+        assert_eq!(events[..expected.len()], expected);
+    }
+
+    #[test]
+    fn fallible() {
+        test_write_fallible(&[1, 2, 3], |mut writer| {
+            writer.write_all(&[1])?;
+            writer.write_all(&[2, 3])
+        });
+    }
+
+    #[test]
+    #[should_panic = "the closure returned an IO error instead of writing all data: encoder failed"]
+    fn fallible_early_error() {
+        test_write_fallible(&[1, 2, 3], |mut writer| {
+            writer.write_all(&[1])?;
+            Err(io::Error::new(io::ErrorKind::Other, "encoder failed"))?;
+            writer.write_all(&[2, 3])
+        });
+    }
+
+    #[test]
+    #[should_panic = "too few bytes were written to the writer but it seems unrelated to partial writes"]
+    fn fallible_silent_truncation() {
+        test_write_fallible(&[1, 2, 3], |mut writer| {
+            // buggy: forgets the last byte
+            writer.write_all(&[1, 2])
+        });
+    }
+
+    #[test]
+    fn one_byte_write() {
+        test_write(&[42], |mut writer| { writer.write(&[42]).unwrap(); });
+    }
+
+    #[test]
+    fn one_byte_write_all() {
+        test_write(&[42], |mut writer| writer.write_all(&[42]).unwrap());
+    }
+
+    #[test]
+    fn one_byte_write_past_end() {
+        // like a bounded writer, only the expected byte is accepted
+        test_write(&[42], |mut writer| assert_eq!(writer.write(&[42, 47]).unwrap(), 1));
+    }
+
+    #[test]
+    fn over_offering_writes() {
+        // the consumer has more data buffered and relies on the return value to write one frame
+        let buffered = [1u8, 2, 3, 4, 5];
+        test_write(&[1, 2, 3], |mut writer| {
+            let mut written = 0;
+            while writer.remaining() > 0 {
+                written += writer.write(&buffered[written..]).unwrap();
+            }
+            assert_eq!(written, 3);
+        });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write unexpected data at pos 0"]
+    fn over_offering_wrong_prefix() {
+        test_write(&[1, 2, 3], |mut writer| { writer.write(&[1, 7, 3, 4]).unwrap(); });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write unexpected data at pos 10, probably unrelated to partial writes\naccepted so far: [.., 2, 3, 4, 5, 6, 7, 8, 9]\n"]
+    fn unexpected_data_shows_accepted() {
+        test_write(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11], |mut writer| {
+            writer.write_all(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+            writer.write_all(&[42, 11]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write more data than expected"]
+    fn one_byte_write_all_past_end() {
+        test_write(&[42], |mut writer| writer.write_all(&[42, 47]).unwrap());
+    }
+
+    #[test]
+    #[should_panic = "attempt to write more data than expected"]
+    fn one_byte_write_past_end_two_writes() {
+        test_write(&[42], |mut writer| {
+            writer.write(&[42]).unwrap();
+            writer.write(&[47]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write more data than expected"]
+    fn one_byte_write_past_end_two_write_all() {
+        test_write(&[42], |mut writer| {
+            writer.write_all(&[42]).unwrap();
+            writer.write_all(&[47]).unwrap();
+        });
+    }
+
+    #[test]
+    fn two_byte_write_all() {
+        test_write(&[42, 47], |mut writer| writer.write_all(&[42, 47]).unwrap());
+    }
+
+    #[test]
+    fn two_bytes_two_writes() {
+        test_write(&[42, 47], |mut writer| {
+            writer.write(&[42]).unwrap();
+            writer.write(&[47]).unwrap();
+        });
+    }
+
+    #[test]
+    fn two_bytes_two_write_all() {
+        test_write(&[42, 47], |mut writer| {
+            writer.write_all(&[42]).unwrap();
+            writer.write_all(&[47]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 0 didn't handle partial write"]
+    fn two_bytes_unhandled_partial() {
+        test_write(&[42, 47], |mut writer| {
+            writer.write(&[42, 47]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 0 didn't handle partial write"]
+    fn three_bytes_unhandled_partial() {
+        test_write(&[42, 47, 1], |mut writer| {
+            writer.write(&[42, 47]).unwrap();
+            writer.write_all(&[1]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "first difference at offset 1"]
+    fn unexpected_data_hex_diff() {
+        test_write(&[42, 47], |mut writer| writer.write_all(&[42, 48]).unwrap());
+    }
+
+    #[test]
+    fn search_finds_split_position() {
+        let err = WriteTest::new(&[1, 0, 42, 0]).run_no_panic(|mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            writer.write(&[42, 0]).unwrap();
+        }).unwrap_err();
+
+        assert_eq!(err.panic_message1(), Some("the write call at position 2 didn't handle partial write"));
+        assert_eq!(err.panic_message2(), err.panic_message1());
+        assert_eq!(err.pos().unwrap(), 3);
+    }
+
+    #[test]
+    fn try_test_write_returns_written() {
+        let written = try_test_write(&[1, 2, 3], |mut writer| writer.write_all(&[1, 2, 3]).unwrap()).unwrap();
+        assert_eq!(written, [1, 2, 3]);
+
+        let generator = |i: usize| i as u8;
+        let written = WriteTest::generated(4, &generator).try_run(|mut writer| {
+            let mut data = &[0u8, 1, 2, 3][..];
+            while !data.is_empty() {
+                let written = writer.write(data).unwrap();
+                data = &data[written..];
+            }
+        }).unwrap();
+        assert_eq!(written, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn try_test_write_failure() {
+        let failure = try_test_write(&[1, 0, 42, 0], |mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            writer.write(&[42, 0]).unwrap();
+        }).unwrap_err();
+        assert_eq!(failure.position(), Some(3));
+        assert_eq!(failure.message(), Some("the write call at position 2 didn't handle partial write"));
+        assert!(failure.to_string().contains("failed at position 3: the write call at position 2 didn't handle partial write"));
+    }
+
+    // run by `search_panics_are_quiet` in a subprocess to capture its stderr
+    #[test]
+    #[ignore]
+    fn quiet_search_child() {
+        try_test_write(&[1, 0, 42, 0], |mut writer| {
+            writer.write_all(&[1, 0]).unwrap();
+            writer.write(&[42, 0]).unwrap();
+        }).unwrap_err();
+        // fails only in the sweep, after the breaking pass passed
+        WriteTest::new(&[1, 2, 3, 4]).sweep_first_write().try_run(|mut writer| {
+            let written = writer.write(&[1, 2]).unwrap();
+            if written == 2 {
+                writer.write(&[3, 4]).unwrap();
+            } else {
+                writer.write_all(&[1, 2, 3, 4][written..]).unwrap();
+            }
+        }).unwrap_err();
+    }
+
+    #[test]
+    fn search_panics_are_quiet() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(&["write::tests::quiet_search_child", "--exact", "--ignored", "--nocapture", "--test-threads=1"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // only the panic of the first pass of the first test is printed
+        assert_eq!(stderr.matches("panicked at").count(), 1, "{}", stderr);
+    }
+
+    #[test]
+    fn search_ignores_correct_writes_before_culprit() {
+        let err = WriteTest::new(&[1, 2, 3, 4, 5]).run_no_panic(|mut writer| {
+            let mut data = &[1u8, 2, 3][..];
+            while !data.is_empty() {
+                let written = writer.write(data).unwrap();
+                data = &data[written..];
+            }
+            writer.write(&[4, 5]).unwrap();
+        }).unwrap_err();
+
+        assert_eq!(err.panic_message2(), err.panic_message1());
+        assert_eq!(err.pos().unwrap(), 4);
+    }
+
+    #[test]
+    fn expected_write_sizes() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).expect_write_sizes(&[4, 2]).run(|mut writer| {
+            writer.write_all(&[1, 2, 3, 4]).unwrap();
+            writer.write_all(&[5, 6]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "unexpected sizes of writes, expected: [4, 2], observed: [2, 2, 2]"]
+    fn unexpected_write_sizes() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).expect_write_sizes(&[4, 2]).run(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            writer.write_all(&[3, 4]).unwrap();
+            writer.write_all(&[5, 6]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 0 didn't handle partial write"]
+    fn by_ref_partial_write() {
+        test_write(&[1, 2], |mut writer| {
+            writer.by_ref().write(&[1, 2]).unwrap();
+        });
+    }
+
+    #[test]
+    fn remaining() {
+        test_write(&[1, 2, 3, 4], |mut writer| {
+            assert_eq!(writer.remaining(), 4);
+            let written = writer.write(&[1, 2]).unwrap();
+            assert_eq!(writer.remaining(), 4 - written);
+            writer.write_all(&[1, 2, 3, 4][written..]).unwrap();
+            assert_eq!(writer.remaining(), 0);
+        });
+    }
+
+    #[test]
+    fn expected_flush_offsets() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).expect_flush_offsets(&[4, 6]).run(|mut writer| {
+            writer.write_all(&[1, 2, 3, 4]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&[5, 6]).unwrap();
+            writer.flush().unwrap();
+        });
+    }
+
+    #[test]
+    fn legal_flush_offsets() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).legal_flush_offsets(&[4, 6]).run(|mut writer| {
+            writer.write_all(&[1, 2, 3, 4]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&[5, 6]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "failed at position 1: illegal flush at offset 1, flushing is only allowed at offsets [4, 6]"]
+    fn mid_frame_flush() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).legal_flush_offsets(&[4, 6]).run(|mut writer| {
+            for mut frame in [&[1, 2, 3, 4][..], &[5, 6]].iter().copied() {
+                while !frame.is_empty() {
+                    let written = writer.write(frame).unwrap();
+                    frame = &frame[written..];
+                    // buggy: flushes after each partial write
+                    writer.flush().unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic = "unexpected offsets of flushes, expected: [4, 6], observed: [2, 6]"]
+    fn unexpected_flush_offsets() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).expect_flush_offsets(&[4, 6]).run(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&[3, 4, 5, 6]).unwrap();
+            writer.flush().unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "writer made no progress, likely resending the same data"]
+    fn resending_same_data() {
+        let data = [0u8; 64];
+        WriteTest::new(&data).max_repeated_writes(8).run(|mut writer| {
+            let mut offset = 0;
+            while offset < data.len() {
+                // buggy: should be `offset += written`
+                offset = writer.write(&data[offset..(offset + 2)]).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(all(feature = "rust_1_46", not(feature = "backtrace"), not(miri)))]
+    fn culprit_of_single_byte_retry() {
+        // single-byte writes are never partial, so the culprit is the write that was split
+        let culprit_line = line!() + 4;
+        let err = WriteTest::new(&[1, 2, 3]).run_no_panic(|mut writer| {
+            let data = [1, 2, 3];
+            // buggy: retries only one byte
+            let written = writer.write(&data).unwrap();
+            if written < data.len() {
+                writer.write(&data[written..(written + 1)]).unwrap();
+            }
+        }).unwrap_err();
+        assert_eq!(err.pos(), Some(1));
+        let message = err.to_string();
+        assert!(message.contains(&format!("Most likely culprit in src/write.rs:{}:", culprit_line)), "{}", message);
+    }
+
+    #[test]
+    fn debug() {
+        // the closure relies on the writer being breaking, so it can't be run with whole writes
+        WriteTest::new(&[1, 2, 3, 4]).allow_nondeterministic().run(|mut writer| {
+            writer.write(&[1, 2]).unwrap();
+            assert_eq!(format!("{:?}", writer), "TestWriter { mode: \"breaking\", pos: 1, last_unwritten: 1, remaining: 3, upcoming: [2, 3, 4] }");
+            writer.write_all(&[2, 3, 4]).unwrap();
+        });
+    }
+
+    /// Encodes bytes as hex digits, one input byte produces two output bytes
+    struct Hex<W>(W);
+
+    impl<W: Write> Write for Hex<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match buf.first() {
+                Some(byte) => {
+                    // buggy: ignores partial write of the digits
+                    self.0.write(format!("{:02x}", byte).as_bytes())?;
+                    Ok(1)
+                },
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    /// Passes the data through, but `write_all` is overridden
+    struct DropsBytes<W>(W);
+
+    impl<W: Write> Write for DropsBytes<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            // buggy: drops the bytes that were not written
+            self.0.write(buf).map(drop)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    #[test]
+    #[should_panic = "didn't handle partial write"]
+    fn writer_impl_overridden_write_all() {
+        test_writer_impl(b"hello", b"hello", |sink| Box::new(DropsBytes(sink)));
+    }
+
+    #[test]
+    fn write_all_is_split() {
+        WriteTest::new(&[1, 2, 3, 4]).sweep_first_write().run(|mut writer| {
+            writer.write_all(&[1, 2, 3]).unwrap();
+            writer.write_all(&[4]).unwrap();
+        });
+    }
+
+    #[test]
+    fn writer_impl() {
+        test_writer_impl(b"hello", b"hello", |sink| Box::new(io::BufWriter::new(sink)));
+    }
+
+    #[test]
+    #[should_panic = "didn't handle partial write"]
+    fn writer_impl_partial_write() {
+        test_writer_impl(b"*+", b"2a2b", |sink| Box::new(Hex(sink)));
+    }
+
+    #[test]
+    fn generated() {
+        test_write_with(1000, |i| i as u8, |mut writer| {
+            let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+            writer.write_all(&data).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 256 didn't handle partial write"]
+    fn generated_partial_write() {
+        test_write_with(1000, |i| i as u8, |mut writer| {
+            let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+            writer.write_all(&data[..256]).unwrap();
+            writer.write(&data[256..]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "first difference at offset 4"]
+    fn generated_unexpected_data() {
+        test_write_with(8, |i| i as u8, |mut writer| {
+            writer.write_all(&[0, 1, 2, 3, 5, 5, 6, 7]).unwrap();
+        });
+    }
+
+    #[test]
+    fn sweep_first_write_correct() {
+        WriteTest::new(&[1, 2, 3, 4, 5, 6]).sweep_first_write().run(|mut writer| {
+            writer.write_all(&[1, 2, 3]).unwrap();
+            writer.write_all(&[4, 5, 6]).unwrap();
+        });
+    }
+
+    #[test]
+    fn allow_trailing() {
+        let test = WriteTest::new(&[1, 2, 3]).allow_trailing(|byte| byte == 0);
+        let written = test.try_run(|mut writer| writer.write_all(&[1, 2, 3, 0, 0]).unwrap()).unwrap();
+        assert_eq!(written, [1, 2, 3, 0, 0]);
+        test.run(|mut writer| {
+            let mut data: &[u8] = &[1, 2, 3, 0];
+            while !data.is_empty() {
+                let written = writer.write(data).unwrap();
+                data = &data[written..];
+            }
+        });
+        WriteTest::new(&[1, 2, 3]).allow_trailing(|byte| byte == 0).both_write_paths().run(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            writer.write_all(&[3, 0]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "trailing byte 0x07 at pos 4 is not valid padding"]
+    fn allow_trailing_invalid() {
+        WriteTest::new(&[1, 2, 3]).allow_trailing(|byte| byte == 0).run(|mut writer| {
+            writer.write_all(&[1, 2, 3, 0, 7]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 0 didn't handle partial write"]
+    fn allow_trailing_partial_write() {
+        WriteTest::new(&[1, 2, 3]).allow_trailing(|byte| byte == 0).run(|mut writer| {
+            writer.write(&[1, 2, 3, 0]).unwrap();
+        });
+    }
+
+    #[test]
+    fn buf_writer() {
+        test_write(&[1, 2, 3, 4, 5], |writer| {
+            let mut writer = io::BufWriter::with_capacity(2, writer);
+            writer.write_all(&[1]).unwrap();
+            // larger than the buffer, passed to the inner writer directly
+            writer.write_all(&[2, 3, 4]).unwrap();
+            writer.write_all(&[5]).unwrap();
+            writer.flush().unwrap();
+        });
+    }
+
+    #[test]
+    fn buf_writer_flushed_on_drop() {
+        test_write(&[1, 2, 3], |writer| {
+            let mut writer = io::BufWriter::new(writer);
+            writer.write_all(&[1, 2, 3]).unwrap();
+        });
+    }
+
+    #[test]
+    fn buf_writer_masks_partial_writes() {
+        // documents the limitation: the buffer accepts the whole write so the bug is not found
+        test_write(&[1, 2, 3], |writer| {
+            let mut writer = io::BufWriter::new(writer);
+            writer.write(&[1, 2, 3]).unwrap();
+        });
+    }
+
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl std::fmt::Display for Point {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "({}, {})", self.x, self.y)
+        }
+    }
+
+    #[test]
+    fn write_fmt() {
+        test_write(b"POINT (1, -2)\r\n", |mut writer| {
+            write!(writer, "POINT {}", Point { x: 1, y: -2 }).unwrap();
+            writeln!(writer, "\r").unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write unexpected data at pos 10"]
+    fn write_fmt_unexpected_data() {
+        test_write(b"POINT (1, -2)\r\n", |mut writer| {
+            writeln!(writer, "POINT {}", Point { x: 1, y: 2 }).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 6 didn't handle partial write"]
+    fn write_fmt_mixed_with_write() {
+        test_write(b"POINT (1, -2)\n", |mut writer| {
+            write!(writer, "POINT ").unwrap();
+            // buggy: ignores the number of written bytes
+            writer.write(Point { x: 1, y: -2 }.to_string().as_bytes()).unwrap();
+            writeln!(writer).unwrap();
+        });
+    }
+
+    #[test]
+    #[allow(clippy::unused_io_amount)]
+    fn sanity() {
+        let buggy = |mut writer: super::TestWriter<'_>| {
+            // buggy: ignores the number of written bytes
+            writer.write(&[1, 0]).unwrap();
+        };
+        sanity_write(&[1, 0], buggy);
+        assert!(try_test_write(&[1, 0], buggy).is_err());
+    }
+
+    #[test]
+    #[should_panic = "failed even with a writer accepting whole writes, the bug is unrelated to splitting: attempt to write unexpected data at pos 0"]
+    fn sanity_failure() {
+        sanity_write(&[1, 0], |mut writer| {
+            // buggy: wrong endianness
+            writer.write_all(&1u16.to_be_bytes()).unwrap();
+        });
+    }
+
+    #[test]
+    fn two_bugs() {
+        let displayed = WriteTest::new(&[1, 2, 3, 4]).allow_nondeterministic().try_run(|mut writer| {
+            let first = writer.write(&[1, 2]).unwrap();
+            if first < 2 {
+                writer.write_all(&[1, 2][first..]).unwrap();
+            }
+            let second = writer.write(&[3, 4]).unwrap();
+            if second < 2 {
+                writer.write_all(&[3, 4][second..]).unwrap();
+            }
+            // buggy: the checks of the amounts are not needed
+            assert!(second == 2, "second write was partial");
+            assert!(first == 2, "first write was partial");
+        }).unwrap_err().to_string();
+        assert!(displayed.contains("bug A localized at position 1: first write was partial"), "{}", displayed);
+        assert!(displayed.contains("bug B (breaking pass) localized at position 3: second write was partial"), "{}", displayed);
+    }
+
+    #[test]
+    fn streamed() {
+        WriteTest::streamed(10_000, || io::Read::take(io::repeat(42), 10_000)).run(|mut writer| {
+            for _ in 0..10 {
+                writer.write_all(&[42; 1000]).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic = "the write call at position 2 didn't handle partial write"]
+    #[allow(clippy::unused_io_amount)]
+    fn streamed_partial_write() {
+        WriteTest::streamed(4, || io::Cursor::new([1, 2, 3, 4])).run(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            // buggy: ignores the number of written bytes
+            writer.write(&[3, 4]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "first difference at offset 3"]
+    fn streamed_unexpected_data() {
+        WriteTest::streamed(4, || io::Cursor::new([1, 2, 3, 4])).run(|mut writer| {
+            writer.write_all(&[1, 2, 3, 5]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "attempt to write unexpected data at pos 2, probably unrelated to partial writes\naccepted so far: [1, 2]"]
+    fn streamed_accepted_data() {
+        // displaying the accepted data reads the stream from the beginning again
+        WriteTest::streamed(4, || io::Cursor::new([1, 2, 3, 4])).run(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            writer.write_all(&[4, 3]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "the expected reader ended after 2 bytes but 4 bytes were expected"]
+    fn streamed_reader_ended_early() {
+        WriteTest::streamed(4, || &[1, 2][..]).run(|mut writer| {
+            writer.write_all(&[1, 2, 3, 4]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic = "too few bytes were written to the writer"]
+    fn streamed_encoder_stopped_short() {
+        WriteTest::streamed(4, || io::Cursor::new([1, 2, 3, 4])).run(|mut writer| {
+            writer.write_all(&[1, 2, 3]).unwrap();
+        });
+    }
+
+    #[test]
+    #[allow(clippy::unused_io_amount)]
+    fn typed_payload() {
+        #[derive(Debug, PartialEq)]
+        struct Unfinished(usize);
+
+        let failure = WriteTest::new(&[1, 2]).try_run(|mut writer| {
+            // buggy: ignores the number of written bytes
+            writer.write(&[1, 2]).unwrap();
+            if writer.remaining() > 0 {
+                std::panic::resume_unwind(Box::new(Unfinished(writer.remaining())));
+            }
+        }).unwrap_err();
+        assert_eq!(failure.message(), None);
+        assert_eq!(failure.payload().downcast_ref(), Some(&Unfinished(1)));
+        assert!(failure.into_payload().is::<Unfinished>());
+    }
+
+    #[test]
+    #[should_panic = "passed but failed when run again with whole writes: attempt to write unexpected data at pos 0"]
+    fn nondeterministic() {
+        use std::sync::atomic::{AtomicU8, Ordering};
+
+        static TIMESTAMP: AtomicU8 = AtomicU8::new(0);
+        test_write(&[42, 0], |mut writer| {
+            writer.write_all(&[42, TIMESTAMP.fetch_add(1, Ordering::Relaxed)]).unwrap();
+        });
+    }
+
+    #[test]
+    fn nondeterministic_rerun_failure() {
+        use std::sync::atomic::{AtomicU8, Ordering};
+
+        static TIMESTAMP: AtomicU8 = AtomicU8::new(0);
+        let failure = WriteTest::new(&[42, 0]).try_run(|mut writer| {
+            writer.write_all(&[42, TIMESTAMP.fetch_add(1, Ordering::Relaxed)]).unwrap();
+        }).unwrap_err();
+        assert_eq!(failure.position(), None);
+        assert_eq!(failure.message().map(|message| message.starts_with("attempt to write unexpected data")), Some(true));
+        assert!(failure.to_string().contains("passed but failed when run again with whole writes"), "{}", failure);
+    }
+
+    #[test]
+    fn nondeterministic_trailing() {
+        use std::sync::atomic::{AtomicU8, Ordering};
+
+        static SALT: AtomicU8 = AtomicU8::new(0);
+        let failure = WriteTest::new(&[42]).allow_trailing(|_| true).try_run(|mut writer| {
+            writer.write_all(&[42, SALT.fetch_add(1, Ordering::Relaxed)]).unwrap();
+        }).unwrap_err();
+        assert_eq!(failure.position(), None);
+        assert_eq!(failure.message(), Some("encoder output is non-deterministic across runs, the trailing bytes differ"));
+    }
+
+    #[test]
+    fn allow_nondeterministic() {
+        use std::sync::atomic::{AtomicU8, Ordering};
+
+        static TIMESTAMP: AtomicU8 = AtomicU8::new(0);
+        WriteTest::new(&[42, 0]).allow_nondeterministic().run(|mut writer| {
+            writer.write_all(&[42, TIMESTAMP.fetch_add(1, Ordering::Relaxed)]).unwrap();
+        });
+    }
+
+    #[test]
+    fn summary_counts_calls() {
+        let summary = WriteTest::new(&[1, 2, 3, 4, 5]).try_run_summary(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            let mut body: &[u8] = &[3, 4];
+            while !body.is_empty() {
+                let written = writer.write(body).unwrap();
+                body = &body[written..];
+            }
+            writer.write_all(&[5]).unwrap();
+            writer.flush().unwrap();
+        }).unwrap();
+        assert_eq!(summary.written(), [1, 2, 3, 4, 5]);
+        assert_eq!(summary.write_calls(), 1);
+        assert_eq!(summary.write_all_calls(), 2);
+        assert_eq!(summary.flush_calls(), 1);
+    }
+
+    #[test]
+    fn summary_state() {
+        let summary = try_test_write_summary(&[1, 2, 3, 4], |mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&[3, 4]).unwrap();
+            writer.flush().unwrap();
+        }).unwrap();
+        assert_eq!(summary.pos(), 4);
+        assert_eq!(summary.last_unwritten(), 0);
+        assert_eq!(summary.write_sizes(), [2, 2]);
+        assert_eq!(summary.flush_offsets(), [2, 4]);
+    }
+
+    #[test]
+    fn both_write_paths() {
+        WriteTest::new(&[1, 2, 3, 4, 5]).both_write_paths().run(|mut writer| {
+            writer.write_all(&[1, 2]).unwrap();
+            let mut body: &[u8] = &[3, 4, 5];
+            while !body.is_empty() {
+                let written = writer.write(body).unwrap();
+                body = &body[written..];
+            }
+        });
+    }
+
+    #[test]
+    fn both_write_paths_reports_variant() {
+        // the closure is only buggy in the variant run, simulating a bug in a branch that's
+        // reachable only when `write_all` accepts whole data
+        let err = WriteTest::new(&[1, 2, 3, 4]).both_write_paths().run_no_panic(|mut writer| {
+            if !writer.config.whole_write_all {
+                writer.write_all(&[1, 2, 3, 4]).unwrap();
+            } else {
+                writer.write_all(&[1, 2]).unwrap();
+                // buggy: ignores partial writes
+                writer.write(&[3, 4]).unwrap();
+            }
+        }).unwrap_err();
+        assert!(err.whole_write_all);
+        let message = err.to_string();
+        assert!(message.contains("(with `write_all` accepting whole data) failed at position 3"), "{}", message);
+    }
+
+    #[test]
+    fn sweep_first_write() {
+        let err = WriteTest::new(&[1, 2, 3, 4]).sweep_first_write().run_no_panic(|mut writer| {
+            let written = writer.write(&[1, 2]).unwrap();
+            if written == 2 {
+                // buggy: assumes the rest is written whole if the header was
+                writer.write(&[3, 4]).unwrap();
+            } else {
+                writer.write_all(&[1, 2, 3, 4][written..]).unwrap();
+            }
+        }).unwrap_err();
+        assert_eq!(err.panic_message1(), Some("the write call at position 2 didn't handle partial write"));
+        assert_eq!(err.first_chunk, Some(2));
     }
 }